@@ -0,0 +1,897 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::{Diagnostic, TYPE_ERROR},
+    expression::{Expr, ExprVisitor, Stmt, StmtVisitor},
+    token::{Span, Token, TokenType, Value},
+};
+
+// The static types known to `rustlox check`. Anything unannotated is `Any`,
+// which is compatible with everything so that untyped code is left alone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Number,
+    String,
+    Bool,
+    Nil,
+    Tuple,
+    Range,
+    // `known` is false for a plain `Function` annotation, which says nothing
+    // about what the function takes or returns
+    Function {
+        params: Vec<Type>,
+        ret: Box<Type>,
+        known: bool,
+    },
+}
+
+impl Type {
+    fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "Any" => Some(Type::Any),
+            "Number" => Some(Type::Number),
+            "String" => Some(Type::String),
+            "Bool" => Some(Type::Bool),
+            "Nil" => Some(Type::Nil),
+            "Tuple" => Some(Type::Tuple),
+            "Range" => Some(Type::Range),
+            "Function" => Some(Type::Function {
+                params: vec![],
+                ret: Box::new(Type::Any),
+                known: false,
+            }),
+            _ => None,
+        }
+    }
+
+    fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function { .. }, Type::Function { .. }) => true,
+            _ => self == other,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => f.write_str("Any"),
+            Type::Number => f.write_str("Number"),
+            Type::String => f.write_str("String"),
+            Type::Bool => f.write_str("Bool"),
+            Type::Nil => f.write_str("Nil"),
+            Type::Tuple => f.write_str("Tuple"),
+            Type::Range => f.write_str("Range"),
+            Type::Function {
+                params: _,
+                ret: _,
+                known: _,
+            } => f.write_str("Function"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub token: Token,
+    pub message: String,
+    // What to underline, which is the token unless a whole expression is at fault
+    pub span: Span,
+}
+
+impl TypeError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(TYPE_ERROR, self.message.clone(), self.span)
+    }
+}
+
+pub struct TypeChecker {
+    // Each scope maps a name to the type it was declared with
+    scopes: Vec<HashMap<String, Type>>,
+    // The declared return type of each function being checked, innermost last
+    return_types: Vec<Type>,
+    errors: Vec<TypeError>,
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        let globals = HashMap::from([
+            (
+                "clock".to_string(),
+                Type::Function {
+                    params: vec![],
+                    ret: Box::new(Type::Number),
+                    known: true,
+                },
+            ),
+            (
+                "variant".to_string(),
+                Type::Function {
+                    params: vec![Type::Any],
+                    ret: Box::new(Type::String),
+                    known: true,
+                },
+            ),
+            (
+                "step".to_string(),
+                Type::Function {
+                    params: vec![Type::Range, Type::Number],
+                    ret: Box::new(Type::Range),
+                    known: true,
+                },
+            ),
+        ]);
+
+        TypeChecker {
+            scopes: vec![globals],
+            return_types: vec![],
+            errors: vec![],
+        }
+    }
+
+    pub fn check(&mut self, statements: &[Stmt]) -> Vec<TypeError> {
+        self.check_statements(statements);
+        self.errors.clone()
+    }
+
+    fn check_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        expr.accept(self)
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token, declared_type: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.to_string(), declared_type);
+        }
+    }
+
+    fn lookup(&self, name: &Token) -> Type {
+        for scope in self.scopes.iter().rev() {
+            if let Some(declared_type) = scope.get(&name.lexeme) {
+                return declared_type.clone();
+            }
+        }
+
+        Type::Any
+    }
+
+    // Turns an optional annotation into a type, reporting names we don't know
+    fn annotated_type(&mut self, annotation: &Option<Token>) -> Type {
+        match annotation {
+            Some(type_name) => match Type::from_name(&type_name.lexeme) {
+                Some(annotated) => annotated,
+                None => {
+                    self.error(type_name, format!("Unknown type '{}'.", type_name.lexeme));
+                    Type::Any
+                }
+            },
+            None => Type::Any,
+        }
+    }
+
+    fn expect(&mut self, token: &Token, expected: &Type, actual: &Type, context: &str) {
+        self.expect_at(token, token.span, expected, actual, context)
+    }
+
+    fn expect_at(
+        &mut self,
+        token: &Token,
+        span: Span,
+        expected: &Type,
+        actual: &Type,
+        context: &str,
+    ) {
+        if !expected.accepts(actual) {
+            self.errors.push(TypeError {
+                token: token.clone(),
+                message: format!("Expected {} {}, but got {}.", expected, context, actual),
+                span,
+            });
+        }
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.errors.push(TypeError {
+            token: token.clone(),
+            message,
+            span: token.span,
+        });
+    }
+}
+
+impl ExprVisitor<Type> for TypeChecker {
+    fn visit_assign_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Assign {
+            name,
+            value,
+            span: _,
+        } = expr
+        {
+            let value_type = self.check_expr(value);
+            let declared_type = self.lookup(name);
+
+            self.expect(
+                name,
+                &declared_type,
+                &value_type,
+                &format!("for '{}'", name.lexeme),
+            );
+
+            value_type
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_binary_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Binary {
+            left,
+            operator,
+            right,
+            span: _,
+        } = expr
+        {
+            let left_type = self.check_expr(left);
+            let right_type = self.check_expr(right);
+
+            match operator.ttype {
+                TokenType::EQUAL_EQUAL | TokenType::BANG_EQUAL | TokenType::IN => Type::Bool,
+                TokenType::PLUS => match (&left_type, &right_type) {
+                    // Strings concatenate with anything on their right
+                    (Type::String, _) => Type::String,
+                    (Type::Number, Type::Number) => Type::Number,
+                    (Type::Any, _) | (_, Type::Any) => Type::Any,
+                    _ => {
+                        self.error(
+                            operator,
+                            format!("Cannot add {} and {}.", left_type, right_type),
+                        );
+                        Type::Any
+                    }
+                },
+                TokenType::DOT_DOT | TokenType::DOT_DOT_EQUAL => {
+                    self.expect(operator, &Type::Number, &left_type, "range bound");
+                    self.expect(operator, &Type::Number, &right_type, "range bound");
+                    Type::Range
+                }
+                TokenType::GREATER
+                | TokenType::GREATER_EQUAL
+                | TokenType::LESS
+                | TokenType::LESS_EQUAL => {
+                    self.expect(operator, &Type::Number, &left_type, "operand");
+                    self.expect(operator, &Type::Number, &right_type, "operand");
+                    Type::Bool
+                }
+                _ => {
+                    self.expect(operator, &Type::Number, &left_type, "operand");
+                    self.expect(operator, &Type::Number, &right_type, "operand");
+                    Type::Number
+                }
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Grouping {
+            expression,
+            span: _,
+        } = expr
+        {
+            self.check_expr(expression)
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_literal_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Literal { value, span: _ } = expr {
+            match value {
+                Value::Boolean { value: _ } => Type::Bool,
+                Value::Double { value: _ } => Type::Number,
+                Value::String { value: _ } => Type::String,
+                Value::Nil => Type::Nil,
+                _ => Type::Any,
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_unary_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Unary {
+            operator,
+            right,
+            span: _,
+        } = expr
+        {
+            let right_type = self.check_expr(right);
+
+            match operator.ttype {
+                TokenType::BANG => Type::Bool,
+                _ => {
+                    self.expect(operator, &Type::Number, &right_type, "operand");
+                    Type::Number
+                }
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_variable_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Variable { name, span: _ } = expr {
+            self.lookup(name)
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_logical_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Logical {
+            left,
+            operator: _,
+            right,
+            span: _,
+        } = expr
+        {
+            let left_type = self.check_expr(left);
+            let right_type = self.check_expr(right);
+
+            if left_type == right_type {
+                left_type
+            } else {
+                Type::Any
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_call_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Call {
+            callee,
+            paren,
+            arguments,
+            optional: _,
+            span: _,
+        } = expr
+        {
+            let callee_type = self.check_expr(callee);
+            let argument_types: Vec<Type> = arguments
+                .iter()
+                .map(|argument| self.check_expr(argument))
+                .collect();
+
+            match callee_type {
+                Type::Function {
+                    params,
+                    ret,
+                    known: true,
+                } => {
+                    if params.len() != argument_types.len() {
+                        self.error(
+                            paren,
+                            format!(
+                                "Expected {} argument{}, but got {}.",
+                                params.len(),
+                                if params.len() == 1 { "" } else { "s" },
+                                argument_types.len()
+                            ),
+                        );
+                    } else {
+                        for ((param, argument_type), argument) in params
+                            .iter()
+                            .zip(argument_types.iter())
+                            .zip(arguments.iter())
+                        {
+                            self.expect_at(
+                                paren,
+                                argument.span(),
+                                param,
+                                argument_type,
+                                "argument",
+                            );
+                        }
+                    }
+
+                    *ret
+                }
+                Type::Any | Type::Function { .. } => Type::Any,
+                _ => {
+                    self.error(
+                        paren,
+                        format!("Cannot call a value of type {}.", callee_type),
+                    );
+                    Type::Any
+                }
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_tuple_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Tuple { elements, span: _ } = expr {
+            for element in elements.iter() {
+                self.check_expr(element);
+            }
+
+            Type::Tuple
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_get_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Get {
+            object,
+            name: _,
+            span: _,
+        } = expr
+        {
+            self.check_expr(object);
+            Type::Any
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_index_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Index {
+            object,
+            bracket,
+            index,
+            span: _,
+        } = expr
+        {
+            let object_type = self.check_expr(object);
+            let index_type = self.check_expr(index);
+
+            self.expect(bracket, &Type::String, &object_type, "to index");
+
+            if !Type::Range.accepts(&index_type) {
+                self.expect(bracket, &Type::Number, &index_type, "index");
+            }
+
+            Type::String
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_slice_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Slice {
+            object,
+            bracket,
+            start,
+            end,
+            span: _,
+        } = expr
+        {
+            let object_type = self.check_expr(object);
+            self.expect(bracket, &Type::String, &object_type, "to slice");
+
+            for bound in [start, end].into_iter().flatten() {
+                let bound_type = self.check_expr(bound);
+                self.expect(bracket, &Type::Number, &bound_type, "slice bound");
+            }
+
+            Type::String
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_if_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: _,
+        } = expr
+        {
+            self.check_expr(condition);
+            let then_type = self.check_expr(then_branch);
+            let else_type = match else_branch {
+                Some(else_expr) => self.check_expr(else_expr),
+                None => Type::Nil,
+            };
+
+            if then_type == else_type {
+                then_type
+            } else {
+                Type::Any
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_block_expr(&mut self, expr: &Expr) -> Type {
+        if let Expr::Block {
+            statements,
+            value,
+            span: _,
+        } = expr
+        {
+            self.begin_scope();
+            self.check_statements(statements);
+            let value_type = match value {
+                Some(value_expr) => self.check_expr(value_expr),
+                None => Type::Nil,
+            };
+            self.end_scope();
+
+            value_type
+        } else {
+            panic!("Nope!")
+        }
+    }
+}
+
+impl StmtVisitor<()> for TypeChecker {
+    fn visit_expression_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Expression { expr, span: _ } = stmt {
+            self.check_expr(expr);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Print { expr, span: _ } = stmt {
+            self.check_expr(expr);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_variable_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Var {
+            name,
+            initializer,
+            constant,
+            type_annotation,
+            span: _,
+        } = stmt
+        {
+            let declared_type = self.annotated_type(type_annotation);
+            let initializer_type = initializer
+                .as_ref()
+                .map(|initializer_expression| self.check_expr(initializer_expression));
+
+            if let Some(initializer_type) = &initializer_type {
+                self.expect(
+                    name,
+                    &declared_type,
+                    initializer_type,
+                    &format!("for '{}'", name.lexeme),
+                );
+            }
+
+            // An unannotated variable can be reassigned to anything, but a
+            // constant keeps the type of its initializer
+            match (&declared_type, initializer_type) {
+                (Type::Any, Some(initializer_type)) if *constant => {
+                    self.declare(name, initializer_type)
+                }
+                _ => self.declare(name, declared_type),
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_block_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Block {
+            statements,
+            span: _,
+        } = stmt
+        {
+            self.begin_scope();
+            self.check_statements(statements);
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: _,
+        } = stmt
+        {
+            self.check_expr(condition);
+            then_branch.accept(self);
+
+            if let Some(else_stmt) = else_branch {
+                else_stmt.accept(self);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::While {
+            condition,
+            body,
+            span: _,
+        } = stmt
+        {
+            self.check_expr(condition);
+            body.accept(self);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Function {
+            name,
+            params,
+            body,
+            decorators,
+            return_type,
+            span: _,
+        } = stmt
+        {
+            for decorator in decorators.iter() {
+                self.check_expr(decorator);
+            }
+
+            let param_types: Vec<Type> = params
+                .iter()
+                .map(|param| self.annotated_type(&param.type_annotation))
+                .collect();
+            let ret = self.annotated_type(return_type);
+
+            // A decorator may replace the function with anything, so we only know
+            // its signature when it isn't decorated
+            if decorators.is_empty() {
+                self.declare(
+                    name,
+                    Type::Function {
+                        params: param_types.clone(),
+                        ret: Box::new(ret.clone()),
+                        known: true,
+                    },
+                );
+            } else {
+                self.declare(name, Type::Any);
+            }
+
+            self.begin_scope();
+            for (param, param_type) in params.iter().zip(param_types) {
+                self.declare(&param.name, param_type);
+            }
+            self.return_types.push(ret);
+            self.check_statements(body);
+            self.return_types.pop();
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Return {
+            keyword,
+            value,
+            span: _,
+        } = stmt
+        {
+            let value_type = self.check_expr(value);
+
+            if let Some(return_type) = self.return_types.last().cloned() {
+                self.expect(keyword, &return_type, &value_type, "return value");
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_assign_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Assign {
+            names,
+            values,
+            span: _,
+        } = stmt
+        {
+            for (name, value) in names.iter().zip(values.iter()) {
+                let value_type = self.check_expr(value);
+                let declared_type = self.lookup(name);
+
+                self.expect(
+                    name,
+                    &declared_type,
+                    &value_type,
+                    &format!("for '{}'", name.lexeme),
+                );
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_unpack_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Unpack {
+            names,
+            paren,
+            initializer,
+            constant: _,
+            span: _,
+        } = stmt
+        {
+            let initializer_type = self.check_expr(initializer);
+            self.expect(paren, &Type::Tuple, &initializer_type, "to unpack");
+
+            for name in names.iter() {
+                self.declare(name, Type::Any);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_enum_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Enum {
+            name,
+            variants: _,
+            span: _,
+        } = stmt
+        {
+            self.declare(name, Type::Any);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::ForIn {
+            name,
+            iterable,
+            body,
+            span: _,
+        } = stmt
+        {
+            let iterable_type = self.check_expr(iterable);
+            self.expect(name, &Type::Range, &iterable_type, "to iterate over");
+
+            self.begin_scope();
+            self.declare(name, Type::Number);
+            body.accept(self);
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_defer_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Defer {
+            keyword: _,
+            stmt,
+            span: _,
+        } = stmt
+        {
+            stmt.accept(self);
+        } else {
+            panic!("Nope!")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        parser::Parser,
+        token::{Scanner, Span},
+    };
+
+    use super::{TypeChecker, TypeError};
+
+    fn check(source: &str) -> Vec<TypeError> {
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        TypeChecker::new().check(&statements)
+    }
+
+    #[test]
+    fn it_accepts_untyped_code() {
+        let errors = check("var a = 1; a = \"one\"; fun f(x) { return x; } print f(a) + 1;");
+
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn it_reports_mismatched_initializers() {
+        let errors = check("var a: Number = \"one\";");
+
+        assert_eq!(1, errors.len());
+        assert_eq!("a", errors[0].token.lexeme);
+        assert_eq!(
+            "Expected Number for 'a', but got String.",
+            errors[0].message
+        );
+    }
+
+    #[test]
+    fn it_reports_mismatched_arguments_and_returns() {
+        let errors = check(
+            "fun add(a: Number, b: Number): Number { return a + b; }\nadd(1, \"two\");\nfun name(): String { return 1; }",
+        );
+
+        assert_eq!(2, errors.len());
+        assert_eq!(2, errors[0].token.line);
+        assert_eq!(Span::new(63, 68), errors[0].span);
+        assert_eq!(
+            "Expected Number argument, but got String.",
+            errors[0].message
+        );
+        assert_eq!(3, errors[1].token.line);
+        assert_eq!(
+            "Expected String return value, but got Number.",
+            errors[1].message
+        );
+    }
+
+    #[test]
+    fn it_reports_wrong_arity_for_known_functions() {
+        let errors = check("fun f(a: Number) {}\nf();");
+
+        assert_eq!(1, errors.len());
+        assert_eq!("Expected 1 argument, but got 0.", errors[0].message);
+    }
+
+    #[test]
+    fn it_reports_wrong_arity_for_functions_without_parameters() {
+        let errors = check("fun f() {}\nf(1, 2);");
+
+        assert_eq!(1, errors.len());
+        assert_eq!("Expected 0 arguments, but got 2.", errors[0].message);
+    }
+
+    #[test]
+    fn it_leaves_calls_through_a_function_annotation_alone() {
+        let errors = check("fun apply(g: Function) { return g(1, 2); }");
+
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn it_reports_unknown_types() {
+        let errors = check("var a: Numbr = 1;");
+
+        assert_eq!(1, errors.len());
+        assert_eq!("Unknown type 'Numbr'.", errors[0].message);
+    }
+
+    #[test]
+    fn it_keeps_the_inferred_type_of_constants() {
+        let errors = check("const a = 1;\nvar b: String = a;");
+
+        assert_eq!(1, errors.len());
+        assert_eq!(2, errors[0].token.line);
+    }
+}
@@ -0,0 +1,501 @@
+use std::io::{self, IsTerminal};
+
+use crate::token::Span;
+
+// Every error, whichever stage found it, is turned into a Diagnostic before it
+// is shown, so that they all render the same way. The code says which stage:
+pub const SCAN_ERROR: &str = "E0001";
+pub const PARSE_ERROR: &str = "E0002";
+pub const RESOLVE_ERROR: &str = "E0003";
+pub const TYPE_ERROR: &str = "E0004";
+pub const RUNTIME_ERROR: &str = "E0005";
+// Problems with how we were run rather than with the script
+pub const USAGE_ERROR: &str = "E0006";
+pub const INPUT_ERROR: &str = "E0007";
+// Warnings have a code for each kind
+pub const UNUSED_VARIABLE: &str = "W0001";
+pub const UNREACHABLE_CODE: &str = "W0002";
+pub const SHADOWING: &str = "W0003";
+pub const CONSTANT_CONDITION: &str = "W0004";
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// How diagnostics are written out: for people, or as JSON for tools
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    // Where the error is, underlined with carets and optionally labelled
+    pub span: Span,
+    pub label: Option<String>,
+    // Other places worth pointing at, like where a variable was declared
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message,
+            span,
+            label: None,
+            secondary: vec![],
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    pub fn warning(code: &'static str, message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, span)
+        }
+    }
+
+    pub fn with_label(mut self, label: String) -> Diagnostic {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn with_secondary_label(mut self, span: Span, message: String) -> Diagnostic {
+        self.secondary.push(Label { span, message });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Diagnostic {
+        self.help.push(help);
+        self
+    }
+
+    // Prints to stderr, either rendered (in colour if that is a terminal) or as JSON
+    pub fn emit(&self, source: &str, path: &str, format: ErrorFormat) {
+        match format {
+            ErrorFormat::Human => {
+                eprint!("{}", self.render(source, path, io::stderr().is_terminal()))
+            }
+            ErrorFormat::Json => eprintln!("{}", self.to_json(source, path)),
+        }
+    }
+
+    // A single line of JSON. Lines and columns are 1-based and counted in
+    // characters, and each end position is just past the end of its span.
+    // Tools depend on these keys, so only ever add to them.
+    pub fn to_json(&self, source: &str, path: &str) -> String {
+        let labels: Vec<String> = self
+            .secondary
+            .iter()
+            .map(|label| {
+                format!(
+                    "{{\"message\":{},{}}}",
+                    json_string(&label.message),
+                    json_position(source, label.span)
+                )
+            })
+            .collect();
+
+        let strings = |texts: &[String]| {
+            texts
+                .iter()
+                .map(|text| json_string(text))
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},{},\"label\":{},\"labels\":[{}],\"notes\":[{}],\"help\":[{}]}}",
+            json_string(self.severity.name()),
+            json_string(self.code),
+            json_string(&self.message),
+            json_string(path),
+            json_position(source, self.span),
+            match &self.label {
+                Some(label) => json_string(label),
+                None => "null".to_string(),
+            },
+            labels.join(","),
+            strings(&self.notes),
+            strings(&self.help)
+        )
+    }
+
+    // Renders like rustc does:
+    //
+    //   error[E0003]: Cannot assign to constant 'a'
+    //    --> script.lox:2:1
+    //     |
+    //   1 | const a = 1;
+    //     |       - constant declared here
+    //   2 | a = 2;
+    //     | ^
+    //     |
+    //     = help: declare it with 'var' to allow assignment
+    pub fn render(&self, source: &str, path: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let (line, column) = position(source, self.span.start);
+
+        // The primary span first, then the labels, each shown under its own line
+        let kind = self.severity.name();
+        let accent = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+
+        let primary = self.label.as_deref().unwrap_or("");
+        let mut annotations = vec![(self.span, '^', accent, primary)];
+        for label in self.secondary.iter() {
+            annotations.push((label.span, '-', BLUE, &label.message));
+        }
+        annotations.sort_by_key(|(span, _, _, _)| position(source, span.start).0);
+
+        let last_line = annotations
+            .iter()
+            .map(|(span, _, _, _)| position(source, span.start).0)
+            .max()
+            .unwrap_or(line);
+        let gutter = " ".repeat(last_line.to_string().len());
+        let bar = paint(BLUE, "|");
+
+        let mut out = format!(
+            "{}{}\n",
+            paint(accent, &format!("{}[{}]", kind, self.code)),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        out += &format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            paint(BLUE, "-->"),
+            path,
+            line,
+            column
+        );
+        out += &format!("{} {}\n", gutter, bar);
+
+        let mut shown_line = 0;
+        for (span, marker, style, message) in annotations.iter() {
+            let (line, column) = position(source, span.start);
+            let text = source.lines().nth(line - 1).unwrap_or("");
+
+            if line != shown_line {
+                out += &format!(
+                    "{} {} {}\n",
+                    paint(BLUE, &format!("{:>width$}", line, width = gutter.len())),
+                    bar,
+                    text
+                );
+                shown_line = line;
+            }
+
+            // Underline up to the end of the span or of the line, whichever is first
+            let end = source[span.start.min(source.len())..span.end.min(source.len())]
+                .chars()
+                .take_while(|c| *c != '\n')
+                .count();
+            let underline = marker.to_string().repeat(end.max(1));
+            let mut annotation = format!("{}{}", " ".repeat(column - 1), underline);
+            if !message.is_empty() {
+                annotation += &format!(" {}", message);
+            }
+
+            out += &format!("{} {} {}\n", gutter, bar, paint(style, &annotation));
+        }
+
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            out += &format!("{} {}\n", gutter, bar);
+        }
+
+        // Lines after the first of a multi-line note line up under its text
+        for (kind, text) in self
+            .notes
+            .iter()
+            .map(|note| ("note", note))
+            .chain(self.help.iter().map(|help| ("help", help)))
+        {
+            let indent = format!("\n{}   {}  ", gutter, " ".repeat(kind.len()));
+            out += &format!(
+                "{} {} {}\n",
+                gutter,
+                paint(BLUE, "="),
+                paint(BOLD, &format!("{}: ", kind)) + &text.replace('\n', &indent)
+            );
+        }
+
+        out
+    }
+}
+
+// The `"line":..,"column":..,"end_line":..,"end_column":..` members for a span
+fn json_position(source: &str, span: Span) -> String {
+    let (line, column) = position(source, span.start);
+    let (end_line, end_column) = position(source, span.end);
+
+    format!(
+        "\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}",
+        line, column, end_line, end_column
+    )
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            '\r' => json += "\\r",
+            '\t' => json += "\\t",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+
+    json + "\""
+}
+
+// The 1-based line and column, in characters, of a byte offset into `source`
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+    (line, column)
+}
+
+// The candidate nearest to `name` by edit distance, for "did you mean" hints.
+// Anything further than a third of the name's length away is too different to
+// be a typo. Ties go to the alphabetically first so the hint is stable.
+pub fn closest_name<'a>(name: &str, candidates: &'a [String]) -> Option<&'a String> {
+    let limit = (name.chars().count() / 3).max(1);
+
+    candidates
+        .iter()
+        .filter(|candidate| candidate.as_str() != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Levenshtein distance, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token::Span;
+
+    use super::{
+        closest_name, edit_distance, json_string, Diagnostic, RESOLVE_ERROR, RUNTIME_ERROR,
+        UNUSED_VARIABLE,
+    };
+
+    #[test]
+    fn it_renders_the_source_line_with_carets_under_the_span() {
+        let source = "var a = 1;\nprint a + nil;\n";
+        let diagnostic = Diagnostic::error(
+            RUNTIME_ERROR,
+            "Operands must be two numbers or two strings.".to_string(),
+            Span::new(19, 20),
+        )
+        .with_label("at '+'".to_string());
+
+        assert_eq!(
+            "error[E0005]: Operands must be two numbers or two strings.\n \
+             --> test.lox:2:9\n  \
+               |\n\
+             2 | print a + nil;\n  \
+               |         ^ at '+'\n",
+            diagnostic.render(source, "test.lox", false)
+        );
+    }
+
+    #[test]
+    fn it_renders_labels_notes_and_help() {
+        let source = "const a = 1;\na = 2;";
+        let diagnostic = Diagnostic::error(
+            RESOLVE_ERROR,
+            "Cannot assign to constant 'a'".to_string(),
+            Span::new(13, 14),
+        )
+        .with_secondary_label(Span::new(6, 7), "constant declared here".to_string())
+        .with_note("first\nsecond".to_string())
+        .with_help("declare it with 'var' to allow assignment".to_string());
+
+        assert_eq!(
+            "error[E0003]: Cannot assign to constant 'a'\n \
+             --> test.lox:2:1\n  \
+               |\n\
+             1 | const a = 1;\n  \
+               |       - constant declared here\n\
+             2 | a = 2;\n  \
+               | ^\n  \
+               |\n  \
+               = note: first\n          \
+                       second\n  \
+               = help: declare it with 'var' to allow assignment\n",
+            diagnostic.render(source, "test.lox", false)
+        );
+    }
+
+    #[test]
+    fn it_only_uses_colour_when_asked() {
+        let diagnostic = Diagnostic::error(RUNTIME_ERROR, "Oops.".to_string(), Span::new(0, 1));
+
+        assert!(!diagnostic.render("x;", "test.lox", false).contains('\x1b'));
+        assert!(diagnostic
+            .render("x;", "test.lox", true)
+            .starts_with("\x1b[1;31merror[E0005]"));
+    }
+
+    #[test]
+    fn it_measures_edit_distance() {
+        assert_eq!(0, edit_distance("counter", "counter"));
+        assert_eq!(1, edit_distance("conter", "counter"));
+        assert_eq!(2, edit_distance("ab", "ba"));
+        assert_eq!(3, edit_distance("", "abc"));
+    }
+
+    #[test]
+    fn it_only_suggests_names_that_are_close() {
+        let names = vec![
+            "counter".to_string(),
+            "count".to_string(),
+            "clock".to_string(),
+        ];
+
+        assert_eq!(Some(&names[0]), closest_name("conter", &names));
+        assert_eq!(Some(&names[1]), closest_name("cont", &names));
+        assert_eq!(None, closest_name("x", &names));
+        assert_eq!(None, closest_name("totally", &names));
+    }
+
+    #[test]
+    fn it_renders_warnings_with_their_own_header() {
+        let diagnostic = Diagnostic::warning(
+            UNUSED_VARIABLE,
+            "Unused variable 'x'.".to_string(),
+            Span::new(6, 7),
+        );
+
+        assert!(diagnostic
+            .render("{ var x = 1; }", "test.lox", false)
+            .starts_with("warning[W0001]: Unused variable 'x'.\n"));
+        assert!(diagnostic
+            .render("{ var x = 1; }", "test.lox", true)
+            .starts_with("\x1b[1;33mwarning[W0001]"));
+    }
+
+    #[test]
+    fn it_serializes_diagnostics_to_json_on_one_line() {
+        let source = "const a = 1;\na = 2;";
+        let diagnostic = Diagnostic::error(
+            RESOLVE_ERROR,
+            "Cannot assign to constant 'a'".to_string(),
+            Span::new(13, 14),
+        )
+        .with_secondary_label(Span::new(6, 7), "constant declared here".to_string())
+        .with_help("declare it with 'var' to allow assignment".to_string());
+
+        assert_eq!(
+            "{\"severity\":\"error\",\"code\":\"E0003\",\"message\":\"Cannot assign to constant 'a'\",\
+             \"file\":\"test.lox\",\"line\":2,\"column\":1,\"end_line\":2,\"end_column\":2,\
+             \"label\":null,\
+             \"labels\":[{\"message\":\"constant declared here\",\"line\":1,\"column\":7,\"end_line\":1,\"end_column\":8}],\
+             \"notes\":[],\
+             \"help\":[\"declare it with 'var' to allow assignment\"]}",
+            diagnostic.to_json(source, "test.lox")
+        );
+    }
+
+    #[test]
+    fn it_serializes_warnings_labels_and_notes_to_json() {
+        let diagnostic = Diagnostic::warning(
+            UNUSED_VARIABLE,
+            "Unused variable 'x'.".to_string(),
+            Span::new(6, 7),
+        )
+        .with_label("here".to_string())
+        .with_note("first\nsecond".to_string());
+
+        assert_eq!(
+            "{\"severity\":\"warning\",\"code\":\"W0001\",\"message\":\"Unused variable 'x'.\",\
+             \"file\":\"dir/a \\\"b\\\".lox\",\"line\":1,\"column\":7,\"end_line\":1,\"end_column\":8,\
+             \"label\":\"here\",\"labels\":[],\"notes\":[\"first\\nsecond\"],\"help\":[]}",
+            diagnostic.to_json("{ var x = 1; }", "dir/a \"b\".lox")
+        );
+    }
+
+    #[test]
+    fn it_escapes_json_strings() {
+        assert_eq!(r#""plain""#, json_string("plain"));
+        assert_eq!(
+            r#""a \"quote\" and \\ slash""#,
+            json_string(r#"a "quote" and \ slash"#)
+        );
+        assert_eq!(r#""tab\tnew\nline\r""#, json_string("tab\tnew\nline\r"));
+        assert_eq!(r#""bell\u0007""#, json_string("bell\u{7}"));
+        assert_eq!(r#""é""#, json_string("é"));
+    }
+}
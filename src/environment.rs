@@ -1,8 +1,13 @@
-use std::{collections::HashMap, rc::Rc, sync::Mutex};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::Mutex,
+};
 
 use log::debug;
 
 use crate::{
+    diagnostic::closest_name,
     interpreter::{ExpressionResult, InterpreterError},
     token::{Token, Value},
 };
@@ -10,6 +15,7 @@ use crate::{
 #[derive(Default, Debug)]
 pub struct Environment {
     values: Mutex<HashMap<String, Value>>,
+    constants: Mutex<HashSet<String>>,
     pub enclosing: Option<Rc<Environment>>,
 }
 
@@ -17,6 +23,7 @@ impl Environment {
     pub fn new(enclosing: Option<Rc<Environment>>) -> Environment {
         Environment {
             values: Mutex::new(HashMap::new()),
+            constants: Mutex::new(HashSet::new()),
             enclosing,
         }
     }
@@ -26,57 +33,109 @@ impl Environment {
 
         let mut values_changer = self.values.lock().unwrap();
         values_changer.insert(name.lexeme.to_string(), value.clone());
+        // Redeclaring a name with `var` makes it mutable again
+        self.constants.lock().unwrap().remove(&name.lexeme);
+    }
+
+    pub fn define_constant(&self, name: &Token, value: &Value) {
+        debug!("Defining constant: {}", name.lexeme);
+
+        let mut values_changer = self.values.lock().unwrap();
+        values_changer.insert(name.lexeme.to_string(), value.clone());
+        self.constants
+            .lock()
+            .unwrap()
+            .insert(name.lexeme.to_string());
     }
 
     pub fn assign(&self, name: &Token, value: &Value) -> ExpressionResult {
+        match self.assign_existing(name, value) {
+            Some(result) => result,
+            None => Err(self.undefined(name)),
+        }
+    }
+
+    // None if the name isn't defined anywhere in the chain
+    fn assign_existing(&self, name: &Token, value: &Value) -> Option<ExpressionResult> {
         debug!("Assigning variable: {}", name.lexeme);
         let mut values_changer = self.values.lock().unwrap();
 
         if values_changer.contains_key(&name.lexeme) {
+            if self.constants.lock().unwrap().contains(&name.lexeme) {
+                return Some(Err(InterpreterError::new_runtime_error(
+                    name,
+                    format!("Cannot assign to constant '{}'", name.lexeme),
+                )));
+            }
+
             values_changer.insert(name.lexeme.to_string(), value.clone());
-            Ok(value.clone())
+            Some(Ok(value.clone()))
         } else {
             match &self.enclosing {
-                Some(enclosing_environment) => enclosing_environment.assign(name, value),
-                None => Err(InterpreterError::new_runtime_error(
-                    name.ttype,
-                    format!("Undefined variable '{}'", name.lexeme),
-                )),
+                Some(enclosing_environment) => enclosing_environment.assign_existing(name, value),
+                None => None,
             }
         }
     }
 
     pub fn get(&self, name: Token) -> ExpressionResult {
+        match self.lookup(&name) {
+            Some(value) => Ok(value),
+            None => Err(self.undefined(&name)),
+        }
+    }
+
+    fn lookup(&self, name: &Token) -> Option<Value> {
         debug!("Getting variable: {}", name.lexeme);
         let values_changer = self.values.lock().unwrap();
         // TODO We should not clone here
         if values_changer.contains_key(&name.lexeme) {
-            Ok(values_changer.get(&name.lexeme).unwrap().clone())
+            Some(values_changer.get(&name.lexeme).unwrap().clone())
         } else {
             match &self.enclosing {
                 Some(enclosing_environment) => {
                     debug!("Getting from enclosing env");
 
-                    enclosing_environment.get(name)
+                    enclosing_environment.lookup(name)
                 }
                 None => {
                     debug!("No enclosing env");
 
-                    Err(InterpreterError::new_runtime_error(
-                        name.ttype,
-                        format!("Undefined variable '{}'", name.lexeme),
-                    ))
+                    None
                 }
             }
         }
     }
+
+    // Suggests the closest name visible from this environment, if any is close enough
+    fn undefined(&self, name: &Token) -> InterpreterError {
+        let mut names = vec![];
+        let mut environment = Some(self);
+
+        while let Some(current) = environment {
+            names.extend(current.values.lock().unwrap().keys().cloned());
+            environment = current.enclosing.as_deref();
+        }
+
+        let message = match closest_name(&name.lexeme, &names) {
+            Some(suggestion) => format!(
+                "Undefined variable '{}'; did you mean '{}'?",
+                name.lexeme, suggestion
+            ),
+            None => format!("Undefined variable '{}'", name.lexeme),
+        };
+
+        InterpreterError::new_runtime_error(name, message)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::rc::Rc;
+
     use crate::{
-        interpreter::RuntimeError,
-        token::{Token, TokenType, Value},
+        interpreter::InterpreterError,
+        token::{Span, Token, TokenType, Value},
     };
 
     use super::Environment;
@@ -88,9 +147,11 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
-        let mut environment = Environment::new(None);
+        let environment = Environment::new(None);
 
         environment.define(&token, &Value::Double { value: 10.0 });
 
@@ -104,12 +165,14 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
-        let mut environment = Environment::new(None);
+        let environment = Environment::new(None);
 
         environment.define(&token, &Value::Double { value: 10.0 });
-        environment.assign(&token, &Value::Double { value: 20.0 });
+        let _ = environment.assign(&token, &Value::Double { value: 20.0 });
 
         assert_eq!(Ok(Value::Double { value: 20.0 }), environment.get(token));
     }
@@ -121,16 +184,18 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let environment = Environment::new(None);
 
         assert_eq!(
             Err(InterpreterError::new_runtime_error(
-                TokenType::IDENTIFIER,
+                &token,
                 "Undefined variable 'foo'".to_string()
             )),
-            environment.get(token)
+            environment.get(token.clone())
         );
     }
 
@@ -141,13 +206,15 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
-        let mut environment = Environment::new(None);
+        let environment = Environment::new(None);
 
         assert_eq!(
             Err(InterpreterError::new_runtime_error(
-                TokenType::IDENTIFIER,
+                &token,
                 "Undefined variable 'foo'".to_string()
             )),
             environment.assign(&token, &Value::Double { value: 20.0 })
@@ -161,6 +228,8 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let bar_token = Token {
@@ -168,12 +237,14 @@ mod tests {
             lexeme: "bar".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let parent_environment = Environment::new(None);
         parent_environment.define(&foo_token, &Value::Double { value: 10.0 });
 
-        let environment = Environment::new(Some(Box::new(parent_environment)));
+        let environment = Environment::new(Some(Rc::new(parent_environment)));
         environment.define(&bar_token, &Value::Double { value: 20.0 });
 
         assert_eq!(
@@ -194,6 +265,8 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let bar_token = Token {
@@ -201,11 +274,13 @@ mod tests {
             lexeme: "bar".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let parent_environment = Environment::new(None);
 
-        let environment = Environment::new(Some(Box::new(parent_environment)));
+        let environment = Environment::new(Some(Rc::new(parent_environment)));
         environment.define(&bar_token, &Value::Double { value: 20.0 });
 
         assert_eq!(
@@ -215,10 +290,10 @@ mod tests {
 
         assert_eq!(
             Err(InterpreterError::new_runtime_error(
-                TokenType::IDENTIFIER,
+                &foo_token,
                 "Undefined variable 'foo'".to_string()
             )),
-            environment.get(foo_token)
+            environment.get(foo_token.clone())
         );
     }
 
@@ -229,6 +304,8 @@ mod tests {
             lexeme: "foo".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let bar_token = Token {
@@ -236,12 +313,14 @@ mod tests {
             lexeme: "bar".to_string(),
             literal: None,
             line: 0,
+            column: 0,
+            span: Span::default(),
         };
 
         let parent_environment = Environment::new(None);
         parent_environment.define(&foo_token, &Value::Double { value: 10.0 });
 
-        let environment = Environment::new(Some(Box::new(parent_environment)));
+        let environment = Environment::new(Some(Rc::new(parent_environment)));
         environment.define(&bar_token, &Value::Double { value: 20.0 });
 
         let _ = environment.assign(&foo_token, &Value::Double { value: 20.0 });
@@ -251,4 +330,70 @@ mod tests {
             environment.get(foo_token)
         );
     }
+
+    #[test]
+    fn it_returns_an_error_if_attempting_to_assign_a_constant() {
+        let token = Token {
+            ttype: TokenType::IDENTIFIER,
+            lexeme: "foo".to_string(),
+            literal: None,
+            line: 0,
+            column: 0,
+            span: Span::default(),
+        };
+
+        let environment = Environment::new(None);
+
+        environment.define_constant(&token, &Value::Double { value: 10.0 });
+
+        assert_eq!(
+            Err(InterpreterError::new_runtime_error(
+                &token,
+                "Cannot assign to constant 'foo'".to_string()
+            )),
+            environment.assign(&token, &Value::Double { value: 20.0 })
+        );
+        assert_eq!(Ok(Value::Double { value: 10.0 }), environment.get(token));
+    }
+
+    #[test]
+    fn it_suggests_the_closest_name_from_any_enclosing_environment() {
+        let counter_token = Token {
+            ttype: TokenType::IDENTIFIER,
+            lexeme: "counter".to_string(),
+            literal: None,
+            line: 0,
+            column: 0,
+            span: Span::default(),
+        };
+
+        let typo_token = Token {
+            ttype: TokenType::IDENTIFIER,
+            lexeme: "conter".to_string(),
+            literal: None,
+            line: 0,
+            column: 0,
+            span: Span::default(),
+        };
+
+        let parent_environment = Environment::new(None);
+        parent_environment.define(&counter_token, &Value::Double { value: 10.0 });
+
+        let environment = Environment::new(Some(Rc::new(parent_environment)));
+
+        assert_eq!(
+            Err(InterpreterError::new_runtime_error(
+                &typo_token,
+                "Undefined variable 'conter'; did you mean 'counter'?".to_string()
+            )),
+            environment.get(typo_token.clone())
+        );
+        assert_eq!(
+            Err(InterpreterError::new_runtime_error(
+                &typo_token,
+                "Undefined variable 'conter'; did you mean 'counter'?".to_string()
+            )),
+            environment.assign(&typo_token, &Value::Nil)
+        );
+    }
 }
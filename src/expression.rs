@@ -1,68 +1,181 @@
-use crate::token::{Token, Value};
+use crate::token::{Span, Token, Value};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Assign {
         name: Token,
         value: Box<Expr>,
+        span: Span,
     },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        span: Span,
     },
     Grouping {
         expression: Box<Expr>,
+        span: Span,
     },
     Literal {
         value: Value,
+        span: Span,
     },
     Logical {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        span: Span,
     },
     Unary {
         operator: Token,
         right: Box<Expr>,
+        span: Span,
     },
     Variable {
         name: Token,
+        span: Span,
     },
     Call {
         callee: Box<Expr>,
         paren: Token,
         arguments: Vec<Expr>,
+        // `f?.(x)` evaluates to nil, without evaluating the arguments, when `f` is nil
+        optional: bool,
+        span: Span,
+    },
+    Tuple {
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+        span: Span,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        span: Span,
+    },
+    // Either bound may be left out, as in `s[:2]` or `s[2:]`
+    Slice {
+        object: Box<Expr>,
+        bracket: Token,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        span: Span,
+    },
+    // `if (c) { a } else { b }` in expression position. Without an else branch
+    // it evaluates to nil when the condition is false.
+    If {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Option<Box<Expr>>,
+        span: Span,
+    },
+    // A block whose final expression, written without a semicolon, is its value
+    Block {
+        statements: Vec<Stmt>,
+        value: Option<Box<Expr>>,
+        span: Span,
     },
 }
 
 impl Expr {
     pub fn accept<A>(&self, visitor: &mut dyn ExprVisitor<A>) -> A {
         match self {
-            Expr::Assign { name: _, value: _ } => visitor.visit_assign_expr(self),
+            Expr::Assign {
+                name: _,
+                value: _,
+                span: _,
+            } => visitor.visit_assign_expr(self),
             Expr::Binary {
                 left: _,
                 operator: _,
                 right: _,
+                span: _,
             } => visitor.visit_binary_expr(self),
-            Expr::Grouping { expression: _ } => visitor.visit_grouping_expr(self),
-            Expr::Literal { value: _ } => visitor.visit_literal_expr(self),
+            Expr::Grouping {
+                expression: _,
+                span: _,
+            } => visitor.visit_grouping_expr(self),
+            Expr::Literal { value: _, span: _ } => visitor.visit_literal_expr(self),
             Expr::Unary {
                 operator: _,
                 right: _,
+                span: _,
             } => visitor.visit_unary_expr(self),
             // TODO replace with Macro?
-            Expr::Variable { name: _ } => visitor.visit_variable_expr(self),
+            Expr::Variable { name: _, span: _ } => visitor.visit_variable_expr(self),
             Expr::Logical {
                 left: _,
                 operator: _,
                 right: _,
+                span: _,
             } => visitor.visit_logical_expr(self),
             Expr::Call {
                 callee: _,
                 paren: _,
                 arguments: _,
+                optional: _,
+                span: _,
             } => visitor.visit_call_expr(self),
+            Expr::Tuple {
+                elements: _,
+                span: _,
+            } => visitor.visit_tuple_expr(self),
+            Expr::Get {
+                object: _,
+                name: _,
+                span: _,
+            } => visitor.visit_get_expr(self),
+            Expr::Index {
+                object: _,
+                bracket: _,
+                index: _,
+                span: _,
+            } => visitor.visit_index_expr(self),
+            Expr::Slice {
+                object: _,
+                bracket: _,
+                start: _,
+                end: _,
+                span: _,
+            } => visitor.visit_slice_expr(self),
+            Expr::If {
+                condition: _,
+                then_branch: _,
+                else_branch: _,
+                span: _,
+            } => visitor.visit_if_expr(self),
+            Expr::Block {
+                statements: _,
+                value: _,
+                span: _,
+            } => visitor.visit_block_expr(self),
+        }
+    }
+
+    // Covers the expression's full source text, e.g. from the left operand of a
+    // binary expression to its right operand
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Grouping { span, .. }
+            | Expr::Literal { span, .. }
+            | Expr::Logical { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Variable { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Tuple { span, .. }
+            | Expr::Get { span, .. }
+            | Expr::Index { span, .. }
+            | Expr::Slice { span, .. }
+            | Expr::If { span, .. }
+            | Expr::Block { span, .. } => *span,
         }
     }
 }
@@ -77,71 +190,197 @@ pub trait ExprVisitor<A> {
     fn visit_variable_expr(&mut self, expr: &Expr) -> A;
     fn visit_logical_expr(&mut self, expr: &Expr) -> A;
     fn visit_call_expr(&mut self, expr: &Expr) -> A;
+    fn visit_tuple_expr(&mut self, expr: &Expr) -> A;
+    fn visit_get_expr(&mut self, expr: &Expr) -> A;
+    fn visit_index_expr(&mut self, expr: &Expr) -> A;
+    fn visit_slice_expr(&mut self, expr: &Expr) -> A;
+    fn visit_if_expr(&mut self, expr: &Expr) -> A;
+    fn visit_block_expr(&mut self, expr: &Expr) -> A;
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Block {
         statements: Vec<Stmt>,
+        span: Span,
     },
     Expression {
         expr: Box<Expr>,
+        span: Span,
     },
     Print {
         expr: Box<Expr>,
+        span: Span,
     },
     Var {
         name: Token,
         initializer: Option<Expr>,
+        constant: bool,
+        // The optional `: Type` after the name, only used by `rustlox check`
+        type_annotation: Option<Token>,
+        span: Span,
     },
     Function {
         name: Token,
-        params: Vec<Token>,
+        params: Vec<Param>,
         body: Vec<Stmt>,
+        // Written top to bottom as `@decorator` lines, applied bottom-up
+        decorators: Vec<Expr>,
+        return_type: Option<Token>,
+        span: Span,
     },
     If {
         condition: Expr,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
+        span: Span,
     },
     While {
         condition: Expr,
         body: Box<Stmt>,
+        span: Span,
     },
     Return {
         keyword: Token,
         value: Box<Expr>,
+        span: Span,
+    },
+    Assign {
+        names: Vec<Token>,
+        values: Vec<Expr>,
+        span: Span,
+    },
+    Unpack {
+        names: Vec<Token>,
+        paren: Token,
+        initializer: Expr,
+        constant: bool,
+        span: Span,
+    },
+    Enum {
+        name: Token,
+        variants: Vec<EnumVariant>,
+        span: Span,
+    },
+    // `for (var name in iterable) body`
+    ForIn {
+        name: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+        span: Span,
     },
+    // `defer stmt;` runs stmt when the enclosing block exits
+    Defer {
+        keyword: Token,
+        stmt: Box<Stmt>,
+        span: Span,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: Token,
+    pub constant: bool,
+    pub type_annotation: Option<Token>,
+}
+
+// A variant without parentheses, like `Empty`, has no fields and is a value
+// rather than a constructor
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariant {
+    pub name: Token,
+    pub fields: Option<Vec<Token>>,
 }
 
 impl Stmt {
     pub fn accept<A>(&self, visitor: &mut dyn StmtVisitor<A>) -> A {
         match self {
-            Stmt::Expression { expr: _ } => visitor.visit_expression_stmt(self),
-            Stmt::Print { expr: _ } => visitor.visit_print_stmt(self),
+            Stmt::Expression { expr: _, span: _ } => visitor.visit_expression_stmt(self),
+            Stmt::Print { expr: _, span: _ } => visitor.visit_print_stmt(self),
             Stmt::Var {
                 name: _,
                 initializer: _,
+                constant: _,
+                type_annotation: _,
+                span: _,
             } => visitor.visit_variable_stmt(self),
-            Stmt::Block { statements: _ } => visitor.visit_block_stmt(self),
+            Stmt::Block {
+                statements: _,
+                span: _,
+            } => visitor.visit_block_stmt(self),
             Stmt::If {
                 condition: _,
                 then_branch: _,
                 else_branch: _,
+                span: _,
             } => visitor.visit_if_stmt(self),
             Stmt::While {
                 condition: _,
                 body: _,
+                span: _,
             } => visitor.visit_while_stmt(self),
             Stmt::Function {
                 name: _,
                 params: _,
                 body: _,
+                decorators: _,
+                return_type: _,
+                span: _,
             } => visitor.visit_function_stmt(self),
             Stmt::Return {
                 keyword: _,
                 value: _,
+                span: _,
             } => visitor.visit_return_stmt(self),
+            Stmt::Assign {
+                names: _,
+                values: _,
+                span: _,
+            } => visitor.visit_assign_stmt(self),
+            Stmt::Unpack {
+                names: _,
+                paren: _,
+                initializer: _,
+                constant: _,
+                span: _,
+            } => visitor.visit_unpack_stmt(self),
+            Stmt::Enum {
+                name: _,
+                variants: _,
+                span: _,
+            } => visitor.visit_enum_stmt(self),
+            Stmt::ForIn {
+                name: _,
+                iterable: _,
+                body: _,
+                span: _,
+            } => visitor.visit_for_in_stmt(self),
+            Stmt::Defer {
+                keyword: _,
+                stmt: _,
+                span: _,
+            } => visitor.visit_defer_stmt(self),
+        }
+    }
+
+    // Runs from the statement's first token up to, but not including, the
+    // semicolon that ends it. Statements a `for` loop is desugared into cover
+    // the whole loop.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Block { span, .. }
+            | Stmt::Expression { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::Function { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::Unpack { span, .. }
+            | Stmt::Enum { span, .. }
+            | Stmt::ForIn { span, .. }
+            | Stmt::Defer { span, .. } => *span,
         }
     }
 }
@@ -155,6 +394,11 @@ pub trait StmtVisitor<A> {
     fn visit_while_stmt(&mut self, stmt: &Stmt) -> A;
     fn visit_function_stmt(&mut self, stmt: &Stmt) -> A;
     fn visit_return_stmt(&mut self, stmt: &Stmt) -> A;
+    fn visit_assign_stmt(&mut self, stmt: &Stmt) -> A;
+    fn visit_unpack_stmt(&mut self, stmt: &Stmt) -> A;
+    fn visit_enum_stmt(&mut self, stmt: &Stmt) -> A;
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) -> A;
+    fn visit_defer_stmt(&mut self, stmt: &Stmt) -> A;
 }
 
 // pub struct AstPrinter {}
@@ -5,36 +5,63 @@ use std::{
 };
 
 use crate::{
+    diagnostic::{Diagnostic, RUNTIME_ERROR},
     environment::Environment,
-    expression::{Expr, ExprVisitor, Stmt, StmtVisitor},
-    token::{Callable, Token, TokenType, Value},
+    expression::{EnumVariant, Expr, ExprVisitor, Stmt, StmtVisitor},
+    token::{Callable, Span, Token, TokenType, Value},
 };
 
 pub struct Interpreter {
+    #[allow(dead_code)]
     pub global: Rc<Environment>,
     environment: Rc<Environment>,
+    // The Lox functions (and natives) currently being called, outermost first
+    frames: Vec<Frame>,
+    // A copy of `frames` taken where the current runtime error was raised, since
+    // they have all been popped by the time the error reaches `interpret`
+    traceback: Option<Vec<Frame>>,
+    // Statements scheduled with `defer`, one list per block being executed
+    deferred: Vec<Vec<Stmt>>,
+    // How many of those lists belong to the callers of the current function
+    deferred_base: usize,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum InterpreterError {
+    // Tokens and values are boxed to keep this small, since every Result the
+    // interpreter passes around carries one
     RuntimeError {
-        // TODO Replace operator with token so we can print the line number in the error
-        operator: TokenType,
+        // The token the error is reported at, which gives us its line
+        token: Box<Token>,
         error: String,
     },
     Return {
         value: Value,
     },
+    // A call in tail position, made by the enclosing Callable::call once the
+    // current function's frame has been unwound
+    TailCall {
+        callee: Box<Value>,
+        paren: Box<Token>,
+        arguments: Vec<Value>,
+    },
 }
 
 impl InterpreterError {
-    pub fn new_runtime_error(operator: TokenType, error: String) -> Self {
-        Self::RuntimeError { operator, error }
+    pub fn new_runtime_error(token: &Token, error: String) -> Self {
+        Self::RuntimeError {
+            token: Box::new(token.clone()),
+            error,
+        }
     }
+}
 
-    // fn to_string(&self) -> String {
-    //     format!("Error: {} ({})", self.error, self.operator)
-    // }
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    pub native: bool,
+    // The line of the call that created this frame
+    pub call_line: i64,
 }
 
 pub type StatementResult = Result<(), InterpreterError>;
@@ -47,6 +74,10 @@ impl Interpreter {
         let interpreter = Interpreter {
             environment: Rc::clone(&env),
             global: Rc::clone(&env),
+            deferred: vec![],
+            deferred_base: 0,
+            frames: vec![],
+            traceback: None,
         };
 
         // Native function definitions
@@ -56,12 +87,15 @@ impl Interpreter {
                 lexeme: "clock".to_string(),
                 literal: None,
                 line: 0,
+                column: 0,
+                span: Span::default(),
             },
             &Value::Callable {
                 callable: Callable::NativeFunction {
+                    name: "clock".to_string(),
                     arity: 0,
                     call: {
-                        |_interpreter, _arguments| {
+                        |_interpreter, _paren, _arguments| {
                             let start = SystemTime::now();
                             let since_the_epoch = start
                                 .duration_since(UNIX_EPOCH)
@@ -77,19 +111,169 @@ impl Interpreter {
             },
         );
 
+        interpreter.environment.define(
+            &Token {
+                ttype: TokenType::IDENTIFIER,
+                lexeme: "step".to_string(),
+                literal: None,
+                line: 0,
+                column: 0,
+                span: Span::default(),
+            },
+            &Value::Callable {
+                callable: Callable::NativeFunction {
+                    name: "step".to_string(),
+                    arity: 2,
+                    call: {
+                        |_interpreter, paren, arguments| match (&arguments[0], &arguments[1]) {
+                            (
+                                Value::Range {
+                                    start,
+                                    end,
+                                    step: _,
+                                    inclusive,
+                                },
+                                Value::Double { value: step },
+                            ) if step.fract() == 0.0 && *step != 0.0 => Ok(Value::Range {
+                                start: *start,
+                                end: *end,
+                                step: *step as i64,
+                                inclusive: *inclusive,
+                            }),
+                            _ => Err(InterpreterError::new_runtime_error(
+                                paren,
+                                "Can only step a range by a non-zero integer.".to_owned(),
+                            )),
+                        }
+                    },
+                    value: "<native fn>".to_owned(),
+                },
+            },
+        );
+
+        interpreter.environment.define(
+            &Token {
+                ttype: TokenType::IDENTIFIER,
+                lexeme: "variant".to_string(),
+                literal: None,
+                line: 0,
+                column: 0,
+                span: Span::default(),
+            },
+            &Value::Callable {
+                callable: Callable::NativeFunction {
+                    name: "variant".to_string(),
+                    arity: 1,
+                    call: {
+                        |_interpreter, paren, arguments| match &arguments[0] {
+                            Value::Variant {
+                                enum_name: _,
+                                variant,
+                                fields: _,
+                                values: _,
+                            } => Ok(Value::String {
+                                value: variant.clone(),
+                            }),
+                            _ => Err(InterpreterError::new_runtime_error(
+                                paren,
+                                "Can only get the variant of an enum value.".to_owned(),
+                            )),
+                        }
+                    },
+                    value: "<native fn>".to_owned(),
+                },
+            },
+        );
+
         interpreter
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Stmt>) {
-        for statement in statements.into_iter() {
+    // Stops at the first uncaught runtime error, returning it with a traceback
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), Box<Diagnostic>> {
+        for statement in statements.iter() {
             match self.execute(statement) {
                 Ok(_) => (),
-                Err(err) => {
-                    println!("Runtime Error caught at `interpret`: {:?}", err);
-                    break;
+                Err(InterpreterError::RuntimeError { token, error }) => {
+                    let mut diagnostic = Diagnostic::error(RUNTIME_ERROR, error, token.span);
+                    let traceback = self.traceback(token.line);
+
+                    if !traceback.is_empty() {
+                        diagnostic = diagnostic.with_note(format!(
+                            "traceback, most recent call first:\n{}",
+                            traceback.join("\n")
+                        ));
+                    }
+
+                    self.frames.clear();
+                    self.traceback = None;
+                    return Err(Box::new(diagnostic));
                 }
+                // The resolver rejects a return outside of a function, so these never
+                // reach the top level
+                Err(_) => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Called as a Lox function starts, so that its tail calls only wait on its own
+    // defers. Returns the previous base for `leave_function` to restore.
+    pub fn enter_function(&mut self) -> usize {
+        std::mem::replace(&mut self.deferred_base, self.deferred.len())
+    }
+
+    pub fn leave_function(&mut self, base: usize) {
+        self.deferred_base = base;
+    }
+
+    pub fn push_frame(&mut self, name: String, native: bool, call_line: i64) {
+        self.frames.push(Frame {
+            name,
+            native,
+            call_line,
+        });
+    }
+
+    // A tail call takes over the frame of the call it replaces, which was still
+    // made from the same line of the caller
+    pub fn replace_frame(&mut self, name: String) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.name = name;
+        }
+    }
+
+    pub fn pop_frame(&mut self, result: &ExpressionResult) {
+        if let (Err(InterpreterError::RuntimeError { .. }), None) = (result, &self.traceback) {
+            self.traceback = Some(self.frames.clone());
+        }
+
+        self.frames.pop();
+    }
+
+    // Renders the captured frames innermost first. Each function is shown at the
+    // line it had reached, which is the line of the call into the next frame in,
+    // or of the error itself for the innermost one.
+    fn traceback(&self, error_line: i64) -> Vec<String> {
+        let frames = match &self.traceback {
+            Some(frames) if !frames.is_empty() => frames,
+            _ => return vec![],
+        };
+
+        let mut lines = vec![];
+        let mut line = error_line;
+
+        for frame in frames.iter().rev() {
+            if frame.native {
+                lines.push(format!("at {}", frame.name));
+            } else {
+                lines.push(format!("at {} (line {})", frame.name, line));
             }
+            line = frame.call_line;
         }
+
+        lines.push(format!("at <script> (line {})", line));
+        lines
     }
 
     fn execute(&mut self, stmt: &Stmt) -> StatementResult {
@@ -102,11 +286,18 @@ impl Interpreter {
 
     pub fn is_truthy(&self, val: &Value) -> bool {
         match val {
-            Value::Boolean { value } => value.clone(),
+            Value::Boolean { value } => *value,
             Value::Double { value: _ } => true,
             Value::String { value: _ } => true,
             Value::Nil => false,
             Value::Callable { callable: _ } => true,
+            Value::Tuple { values: _ } => true,
+            Value::Range { .. } => true,
+            Value::Enum {
+                name: _,
+                variants: _,
+            } => true,
+            Value::Variant { .. } => true,
         }
     }
 
@@ -126,47 +317,337 @@ impl Interpreter {
         left == right
     }
 
+    // Bitwise operators work on the integer part of a Double, so anything with a
+    // fractional part (or outside of the i64 range) is rejected rather than truncated.
+    fn to_integer(operator: &Token, val: &Value) -> Result<i64, InterpreterError> {
+        match val {
+            Value::Double { value }
+                if value.fract() == 0.0
+                    && *value >= i64::MIN as f64
+                    && *value < i64::MAX as f64 =>
+            {
+                Ok(*value as i64)
+            }
+            _ => Err(InterpreterError::RuntimeError {
+                token: Box::new(operator.clone()),
+                error: "Operands must be integers.".to_string(),
+            }),
+        }
+    }
+
+    // Resolves a possibly negative string index against a string of `length`
+    // characters, where `allow_end` lets slices refer to the position after the last one
+    fn string_position(
+        bracket: &Token,
+        val: &Value,
+        length: usize,
+        allow_end: bool,
+    ) -> Result<usize, InterpreterError> {
+        let index = match val {
+            Value::Double { value } if value.fract() == 0.0 => *value as i64,
+            _ => {
+                return Err(InterpreterError::RuntimeError {
+                    token: Box::new(bracket.clone()),
+                    error: "String indices must be integers.".to_string(),
+                })
+            }
+        };
+
+        let position = if index < 0 {
+            index + length as i64
+        } else {
+            index
+        };
+        let limit = if allow_end {
+            length as i64 + 1
+        } else {
+            length as i64
+        };
+
+        if position < 0 || position >= limit {
+            return Err(InterpreterError::RuntimeError {
+                token: Box::new(bracket.clone()),
+                error: format!("String index {} out of range for length {}.", index, length),
+            });
+        }
+
+        Ok(position as usize)
+    }
+
+    // `s[a..b]` is the same as `s[a:b]`, but the range can also be inclusive or have a step
+    fn slice_with_range(
+        bracket: &Token,
+        characters: &[char],
+        start: i64,
+        end: i64,
+        step: i64,
+        inclusive: bool,
+    ) -> ExpressionResult {
+        if step < 1 {
+            return Err(InterpreterError::RuntimeError {
+                token: Box::new(bracket.clone()),
+                error: "Can only slice with a positive step.".to_string(),
+            });
+        }
+
+        let start_position = Self::string_position(
+            bracket,
+            &Value::Double {
+                value: start as f64,
+            },
+            characters.len(),
+            true,
+        )?;
+        let end_position = match Self::string_position(
+            bracket,
+            &Value::Double { value: end as f64 },
+            characters.len(),
+            !inclusive,
+        ) {
+            Ok(position) if inclusive => position + 1,
+            Ok(position) => position,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Value::String {
+            value: characters
+                .iter()
+                .take(end_position)
+                .skip(start_position)
+                .step_by(step as usize)
+                .collect(),
+        })
+    }
+
+    fn evaluate_string(
+        &mut self,
+        bracket: &Token,
+        object: &Expr,
+    ) -> Result<Vec<char>, InterpreterError> {
+        match self.evaluate(object) {
+            Ok(Value::String { value }) => Ok(value.chars().collect()),
+            Ok(_) => Err(InterpreterError::RuntimeError {
+                token: Box::new(bracket.clone()),
+                error: "Can only index strings.".to_string(),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn range(operator: &Token, start: &Value, end: &Value) -> ExpressionResult {
+        let start_value = Self::to_integer(operator, start)?;
+        let end_value = Self::to_integer(operator, end)?;
+
+        Ok(Value::Range {
+            start: start_value,
+            end: end_value,
+            step: 1,
+            inclusive: operator.ttype == TokenType::DOT_DOT_EQUAL,
+        })
+    }
+
+    // Whether `value` has not yet passed the end of a range walking in `step` sized steps
+    pub fn range_continues(end: i64, step: i64, inclusive: bool, value: i64) -> bool {
+        match (step > 0, inclusive) {
+            (true, true) => value <= end,
+            (true, false) => value < end,
+            (false, true) => value >= end,
+            (false, false) => value > end,
+        }
+    }
+
+    fn contains(operator: &Token, needle: &Value, haystack: &Value) -> ExpressionResult {
+        match haystack {
+            Value::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let contained = match needle {
+                    Value::Double { value } if value.fract() == 0.0 => {
+                        let value = *value as i64;
+                        let started = if *step > 0 {
+                            value >= *start
+                        } else {
+                            value <= *start
+                        };
+
+                        // The offset only overflows for ranges wider than i64, which
+                        // we treat as not containing the value rather than panicking
+                        started
+                            && Self::range_continues(*end, *step, *inclusive, value)
+                            && value
+                                .checked_sub(*start)
+                                .and_then(|offset| offset.checked_rem(*step))
+                                == Some(0)
+                    }
+                    _ => false,
+                };
+
+                Ok(Value::Boolean { value: contained })
+            }
+            _ => Err(InterpreterError::RuntimeError {
+                token: Box::new(operator.clone()),
+                error: "Can only test membership of a range.".to_string(),
+            }),
+        }
+    }
+
+    fn bitwise(&self, operator: &Token, left: &Value, right: &Value) -> ExpressionResult {
+        let left_value = Self::to_integer(operator, left)?;
+        let right_value = Self::to_integer(operator, right)?;
+
+        let result = match operator.ttype {
+            TokenType::AMPERSAND => left_value & right_value,
+            TokenType::PIPE => left_value | right_value,
+            TokenType::CARET => left_value ^ right_value,
+            TokenType::LESS_LESS | TokenType::GREATER_GREATER => {
+                if !(0..64).contains(&right_value) {
+                    return Err(InterpreterError::RuntimeError {
+                        token: Box::new(operator.clone()),
+                        error: "Shift amount must be between 0 and 63.".to_string(),
+                    });
+                }
+
+                if operator.ttype == TokenType::LESS_LESS {
+                    left_value << right_value
+                } else {
+                    left_value >> right_value
+                }
+            }
+            _ => panic!("Not a bitwise operator"),
+        };
+
+        Ok(Value::Double {
+            value: result as f64,
+        })
+    }
+
+    // Evaluates the callee and arguments of a call and checks the arity, leaving
+    // the call itself to the caller. Returns None when an optional call `f?.()`
+    // short-circuits on nil.
+    fn prepare_call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        optional: bool,
+    ) -> Result<Option<(Callable, Vec<Value>)>, InterpreterError> {
+        let callee_res = self.evaluate(callee);
+
+        match callee_res {
+            Ok(Value::Nil) if optional => return Ok(None),
+            Err(err) => return Err(err),
+            _ => (),
+        }
+
+        let mut func_arguments = vec![];
+
+        for arg in arguments.iter() {
+            match self.evaluate(arg) {
+                Ok(arg_value) => func_arguments.push(arg_value),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if let Ok(Value::Callable { callable }) = callee_res {
+            if func_arguments.len() == callable.arity() as usize {
+                Ok(Some((callable, func_arguments)))
+            } else {
+                Err(InterpreterError::RuntimeError {
+                    token: Box::new(paren.clone()),
+                    error: format!(
+                        "Expected {} argument{} to '{}' but got {}.",
+                        callable.arity(),
+                        if callable.arity() == 1 { "" } else { "s" },
+                        callable.name(),
+                        func_arguments.len()
+                    ),
+                })
+            }
+        } else {
+            Err(InterpreterError::RuntimeError {
+                token: Box::new(paren.clone()),
+                error: "Can only call functions and classes.".to_owned(),
+            })
+        }
+    }
+
     // TODO Does this need to return a Return?
     pub fn execute_block(
         &mut self,
-        statements: &Vec<Stmt>,
+        statements: &[Stmt],
         environment: Environment,
     ) -> StatementResult {
+        match self.evaluate_block(statements, None, environment) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Runs a block's statements in the given environment, then evaluates its
+    // final expression, if there is one, before any deferred statements run
+    fn evaluate_block(
+        &mut self,
+        statements: &[Stmt],
+        value: Option<&Expr>,
+        environment: Environment,
+    ) -> ExpressionResult {
         // Create a new env that refers to the current env
         // Replace the current env with the new env
         // Process the statements
         // Reset the env back
-        // //
-        // let parent_env = self.environment.take();
         let prev = Rc::clone(&self.environment);
         self.environment = environment.into();
+        self.deferred.push(vec![]);
+
+        let mut result = Ok(Value::Nil);
+
+        for statement in statements.iter() {
+            match self.execute(statement) {
+                Ok(_) => (),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        if let (Ok(_), Some(value_expr)) = (&result, value) {
+            result = self.evaluate(value_expr);
+        }
 
-        for statement in statements.into_iter() {
-            // TODO Do we need to break out here to return?
-            match self.execute(&statement) {
+        // Deferred statements run last-in first-out in the block's own environment,
+        // however the block exits. An error from one of them is only reported if
+        // the block itself finished cleanly.
+        let deferred = self.deferred.pop().unwrap_or_default();
+        for stmt in deferred.iter().rev() {
+            match self.execute(stmt) {
                 Ok(_) => (),
                 Err(err) => {
-                    // reset environment - TODO Confirm if needed
-                    self.environment = prev;
-                    return Err(err);
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
                 }
             }
         }
 
-        // TODO - I can't remember what I meant by the following comment, maybe it can be removed.
         // TODO - this needs to reset to the previous env, not the enclosing of the current env.
         self.environment = prev;
-        Ok(())
+        result
     }
 }
 
 impl ExprVisitor<ExpressionResult> for Interpreter {
+    #[allow(clippy::question_mark)]
     fn visit_binary_expr(&mut self, expr: &crate::expression::Expr) -> ExpressionResult {
         match expr {
             Expr::Binary {
                 left,
                 operator,
                 right,
+                span: _,
             } => {
                 let left_val = match self.evaluate(left) {
                     Ok(val) => val,
@@ -181,6 +662,17 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
                 match &operator.ttype {
                     TokenType::EQUAL_EQUAL => return Ok(self.is_equal(&left_val, &right_val)),
                     TokenType::BANG_EQUAL => return Ok(self.is_not_equal(&left_val, &right_val)),
+                    TokenType::AMPERSAND
+                    | TokenType::PIPE
+                    | TokenType::CARET
+                    | TokenType::LESS_LESS
+                    | TokenType::GREATER_GREATER => {
+                        return self.bitwise(operator, &left_val, &right_val)
+                    }
+                    TokenType::DOT_DOT | TokenType::DOT_DOT_EQUAL => {
+                        return Self::range(operator, &left_val, &right_val)
+                    }
+                    TokenType::IN => return Self::contains(operator, &left_val, &right_val),
                     _ => (), // do nothing here, evalue the operator based on the left type below
                 }
 
@@ -211,44 +703,56 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
                             TokenType::LESS_EQUAL => Ok(Value::Boolean {
                                 value: left_value <= right_value,
                             }),
-                            op => Err(InterpreterError::RuntimeError {
-                                operator: op,
+                            _ => Err(InterpreterError::RuntimeError {
+                                token: Box::new(operator.clone()),
                                 error: "Cannot perform this operation on a number".to_string(),
                             }),
                         },
                         Value::Boolean { value: _ } => Err(InterpreterError::RuntimeError {
-                            operator: operator.ttype,
+                            token: Box::new(operator.clone()),
                             error: "Cannot perform this with a number and boolean".to_string(),
                         }),
                         Value::String { value: _ } => Err(InterpreterError::RuntimeError {
-                            operator: operator.ttype,
+                            token: Box::new(operator.clone()),
                             error: "Cannot perform this with a number and a string".to_string(),
                         }),
                         Value::Nil => Err(InterpreterError::RuntimeError {
-                            operator: operator.ttype,
+                            token: Box::new(operator.clone()),
                             error: "Cannot perform this with a number and nil".to_string(),
                         }),
                         // TODO - Maybe this is a bug??
                         Value::Callable { callable: _ } => Err(InterpreterError::RuntimeError {
-                            operator: operator.ttype,
+                            token: Box::new(operator.clone()),
                             error: "Cannot perform this with a number and Callable".to_string(),
                         }),
+                        Value::Tuple { values: _ } => Err(InterpreterError::RuntimeError {
+                            token: Box::new(operator.clone()),
+                            error: "Cannot perform this with a number and a tuple".to_string(),
+                        }),
+                        Value::Range { .. } => Err(InterpreterError::RuntimeError {
+                            token: Box::new(operator.clone()),
+                            error: "Cannot perform this with a number and a range".to_string(),
+                        }),
+                        Value::Enum { .. } | Value::Variant { .. } => {
+                            Err(InterpreterError::RuntimeError {
+                                token: Box::new(operator.clone()),
+                                error: "Cannot perform this with a number and an enum".to_string(),
+                            })
+                        }
                     },
                     Value::String { value: left_value } => match operator.ttype {
                         crate::token::TokenType::PLUS => Ok(Value::String {
                             value: left_value.to_string() + &right_val.to_string(),
                         }),
-                        op => Err(InterpreterError::RuntimeError {
-                            operator: op,
+                        _ => Err(InterpreterError::RuntimeError {
+                            token: Box::new(operator.clone()),
                             error: "Cannot perform this operation on a string".to_string(),
                         }),
                     },
-                    _ => match operator.ttype {
-                        op => Err(InterpreterError::RuntimeError {
-                            operator: op,
-                            error: "Cannot perform this operation on this type".to_string(),
-                        }),
-                    },
+                    _ => Err(InterpreterError::RuntimeError {
+                        token: Box::new(operator.clone()),
+                        error: "Cannot perform this operation on this type".to_string(),
+                    }),
                 }
             }
             _ => panic!("NOT A BINARY EXPRESSION"),
@@ -257,21 +761,29 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
 
     fn visit_grouping_expr(&mut self, expr: &crate::expression::Expr) -> ExpressionResult {
         match expr {
-            Expr::Grouping { expression } => self.evaluate(&expression),
+            Expr::Grouping {
+                expression,
+                span: _,
+            } => self.evaluate(expression),
             _ => panic!("Nope!"),
         }
     }
 
     fn visit_literal_expr(&mut self, expr: &crate::expression::Expr) -> ExpressionResult {
         match expr {
-            Expr::Literal { value } => Ok(value.clone()),
+            Expr::Literal { value, span: _ } => Ok(value.clone()),
             _ => panic!("Nope!"),
         }
     }
 
+    #[allow(clippy::question_mark)]
     fn visit_unary_expr(&mut self, expr: &crate::expression::Expr) -> ExpressionResult {
         match expr {
-            Expr::Unary { operator, right } => {
+            Expr::Unary {
+                operator,
+                right,
+                span: _,
+            } => {
                 let right_val = match self.evaluate(right) {
                     Ok(val) => val,
                     Err(runtime_error) => return Err(runtime_error),
@@ -281,11 +793,22 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
                     crate::token::TokenType::MINUS => match right_val {
                         Value::Double { value } => Ok(Value::Double { value: -value }),
                         // We could handle strings here
-                        _ => panic!("Nope!"),
+                        _ => Err(InterpreterError::new_runtime_error(
+                            operator,
+                            "Operand must be a number.".to_string(),
+                        )),
                     },
                     crate::token::TokenType::BANG => Ok(Value::Boolean {
                         value: !self.is_truthy(&right_val),
                     }),
+                    crate::token::TokenType::TILDE => {
+                        match Self::to_integer(operator, &right_val) {
+                            Ok(value) => Ok(Value::Double {
+                                value: !value as f64,
+                            }),
+                            Err(err) => Err(err),
+                        }
+                    }
                     _ => panic!("Nope!"),
                 }
             }
@@ -295,14 +818,18 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
 
     fn visit_variable_expr(&mut self, expr: &Expr) -> ExpressionResult {
         match expr {
-            Expr::Variable { name } => self.environment.get(name.clone()),
+            Expr::Variable { name, span: _ } => self.environment.get(name.clone()),
             _ => panic!("Nope!"),
         }
     }
 
     fn visit_assign_expr(&mut self, expr: &Expr) -> ExpressionResult {
         match expr {
-            Expr::Assign { name, value } => {
+            Expr::Assign {
+                name,
+                value,
+                span: _,
+            } => {
                 let value = self.evaluate(value);
 
                 match value {
@@ -319,13 +846,18 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
             left,
             operator,
             right,
+            span: _,
         } = expr
         {
             let left_result = self.evaluate(left);
 
             match left_result {
                 Ok(left) => {
-                    if operator.ttype == TokenType::OR {
+                    if operator.ttype == TokenType::QUESTION_QUESTION {
+                        if left != Value::Nil {
+                            return Ok(left);
+                        }
+                    } else if operator.ttype == TokenType::OR {
                         if self.is_truthy(&left) {
                             return Ok(left);
                         }
@@ -349,45 +881,221 @@ impl ExprVisitor<ExpressionResult> for Interpreter {
             callee,
             paren,
             arguments,
+            optional,
+            span: _,
+        } = expr
+        {
+            match self.prepare_call(callee, paren, arguments, *optional) {
+                Ok(Some((callable, func_arguments))) => callable.call(self, paren, &func_arguments),
+                Ok(None) => Ok(Value::Nil),
+                Err(err) => Err(err),
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_get_expr(&mut self, expr: &Expr) -> ExpressionResult {
+        if let Expr::Get {
+            object,
+            name,
+            span: _,
         } = expr
         {
-            let callee_res = self.evaluate(&callee);
-            let mut func_arguments = vec![];
+            let object_value = self.evaluate(object)?;
 
-            for arg in arguments.into_iter() {
-                match self.evaluate(arg) {
-                    Ok(arg_value) => func_arguments.push(arg_value),
-                    Err(err) => return Err(err),
+            match object_value {
+                Value::Enum {
+                    name: enum_name,
+                    variants,
+                } => match variants
+                    .iter()
+                    .find(|variant| variant.name.lexeme == name.lexeme)
+                {
+                    Some(EnumVariant {
+                        name: _,
+                        fields: Some(fields),
+                    }) => Ok(Value::Callable {
+                        callable: Callable::EnumConstructor {
+                            enum_name,
+                            variant: name.lexeme.clone(),
+                            fields: fields.iter().map(|field| field.lexeme.clone()).collect(),
+                        },
+                    }),
+                    Some(EnumVariant {
+                        name: _,
+                        fields: None,
+                    }) => Ok(Value::Variant {
+                        enum_name,
+                        variant: name.lexeme.clone(),
+                        fields: vec![],
+                        values: vec![],
+                    }),
+                    None => Err(InterpreterError::RuntimeError {
+                        token: Box::new(name.clone()),
+                        error: format!(
+                            "Undefined variant '{}' on enum '{}'.",
+                            name.lexeme, enum_name
+                        ),
+                    }),
+                },
+                Value::Variant {
+                    enum_name: _,
+                    variant,
+                    fields,
+                    values,
+                } => match fields.iter().position(|field| *field == name.lexeme) {
+                    Some(index) => Ok(values[index].clone()),
+                    None => Err(InterpreterError::RuntimeError {
+                        token: Box::new(name.clone()),
+                        error: format!(
+                            "Undefined field '{}' on variant '{}'.",
+                            name.lexeme, variant
+                        ),
+                    }),
+                },
+                _ => Err(InterpreterError::RuntimeError {
+                    token: Box::new(name.clone()),
+                    error: "Only enums have properties.".to_owned(),
+                }),
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_index_expr(&mut self, expr: &Expr) -> ExpressionResult {
+        if let Expr::Index {
+            object,
+            bracket,
+            index,
+            span: _,
+        } = expr
+        {
+            let characters = self.evaluate_string(bracket, object)?;
+
+            let index_value = self.evaluate(index)?;
+
+            if let Value::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } = index_value
+            {
+                return Self::slice_with_range(bracket, &characters, start, end, step, inclusive);
+            }
+
+            match Self::string_position(bracket, &index_value, characters.len(), false) {
+                Ok(position) => Ok(Value::String {
+                    value: characters[position].to_string(),
+                }),
+                Err(err) => Err(err),
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_slice_expr(&mut self, expr: &Expr) -> ExpressionResult {
+        if let Expr::Slice {
+            object,
+            bracket,
+            start,
+            end,
+            span: _,
+        } = expr
+        {
+            let characters = self.evaluate_string(bracket, object)?;
+
+            let mut bounds = [0, characters.len()];
+
+            for (bound, bound_expr) in bounds.iter_mut().zip([start, end]) {
+                if let Some(bound_expr) = bound_expr {
+                    let bound_value = self.evaluate(bound_expr)?;
+
+                    match Self::string_position(bracket, &bound_value, characters.len(), true) {
+                        Ok(position) => *bound = position,
+                        Err(err) => return Err(err),
+                    }
                 }
             }
 
-            if let Ok(Value::Callable { callable }) = callee_res {
-                if func_arguments.len() == callable.arity() as usize {
-                    callable.call(self, &func_arguments)
-                } else {
-                    return Err(InterpreterError::RuntimeError {
-                        operator: paren.ttype,
-                        // TODO Interpolate this error correctly
-                        error: "Expected x arguments, but got y".to_owned(),
-                    });
+            let [start_position, end_position] = bounds;
+
+            if start_position >= end_position {
+                return Ok(Value::String {
+                    value: String::new(),
+                });
+            }
+
+            Ok(Value::String {
+                value: characters[start_position..end_position].iter().collect(),
+            })
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_tuple_expr(&mut self, expr: &Expr) -> ExpressionResult {
+        if let Expr::Tuple { elements, span: _ } = expr {
+            let mut values = vec![];
+
+            for element in elements.iter() {
+                match self.evaluate(element) {
+                    Ok(value) => values.push(value),
+                    Err(err) => return Err(err),
                 }
+            }
+
+            Ok(Value::Tuple { values })
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_if_expr(&mut self, expr: &Expr) -> ExpressionResult {
+        if let Expr::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: _,
+        } = expr
+        {
+            let value = self.evaluate(condition)?;
+
+            if self.is_truthy(&value) {
+                self.evaluate(then_branch)
+            } else if let Some(else_expr) = else_branch {
+                self.evaluate(else_expr)
             } else {
-                return Err(InterpreterError::RuntimeError {
-                    operator: paren.ttype,
-                    error: "Can only call functions and classes.".to_owned(),
-                });
+                Ok(Value::Nil)
             }
         } else {
             panic!("Nope!")
         }
     }
+
+    fn visit_block_expr(&mut self, expr: &Expr) -> ExpressionResult {
+        if let Expr::Block {
+            statements,
+            value,
+            span: _,
+        } = expr
+        {
+            let environment = Environment::new(Some(Rc::clone(&self.environment)));
+            self.evaluate_block(statements, value.as_deref(), environment)
+        } else {
+            panic!("Nope!")
+        }
+    }
 }
 
 impl StmtVisitor<StatementResult> for Interpreter {
     fn visit_expression_stmt(&mut self, stmt: &crate::expression::Stmt) -> StatementResult {
         // println!("Visiting expression statement");
         match stmt {
-            Stmt::Expression { expr } => {
+            Stmt::Expression { expr, span: _ } => {
                 // TODO statements should raise errors
                 match self.evaluate(expr) {
                     Ok(_) => Ok(()),
@@ -400,7 +1108,7 @@ impl StmtVisitor<StatementResult> for Interpreter {
 
     fn visit_print_stmt(&mut self, stmt: &crate::expression::Stmt) -> StatementResult {
         match stmt {
-            Stmt::Print { expr } => match self.evaluate(expr) {
+            Stmt::Print { expr, span: _ } => match self.evaluate(expr) {
                 Ok(value) => {
                     println!("{}", value);
                     Ok(())
@@ -411,24 +1119,32 @@ impl StmtVisitor<StatementResult> for Interpreter {
         }
     }
 
+    #[allow(clippy::question_mark)]
     fn visit_variable_stmt(&mut self, stmt: &Stmt) -> StatementResult {
         match stmt {
-            Stmt::Var { name, initializer } => {
+            Stmt::Var {
+                name,
+                initializer,
+                constant,
+                type_annotation: _,
+                span: _,
+            } => {
                 // TODO statements should raise errors
-
-                match initializer {
+                let value = match initializer {
                     Some(initializer_expression) => match self.evaluate(initializer_expression) {
-                        Ok(value) => {
-                            self.environment.define(&name, &value);
-                            Ok(())
-                        }
-                        Err(err) => Err(err),
+                        Ok(value) => value,
+                        Err(err) => return Err(err),
                     },
-                    None => {
-                        self.environment.define(&name, &Value::Nil);
-                        Ok(())
-                    }
+                    None => Value::Nil,
+                };
+
+                if *constant {
+                    self.environment.define_constant(name, &value);
+                } else {
+                    self.environment.define(name, &value);
                 }
+
+                Ok(())
             }
             _ => panic!("Nope!"),
         }
@@ -436,7 +1152,10 @@ impl StmtVisitor<StatementResult> for Interpreter {
 
     fn visit_block_stmt(&mut self, stmt: &Stmt) -> StatementResult {
         match stmt {
-            Stmt::Block { statements } => {
+            Stmt::Block {
+                statements,
+                span: _,
+            } => {
                 let env = Environment::new(Some(Rc::clone(&self.environment)));
                 self.execute_block(statements, env)
             }
@@ -444,16 +1163,22 @@ impl StmtVisitor<StatementResult> for Interpreter {
         }
     }
 
+    #[allow(clippy::question_mark)]
     fn visit_if_stmt(&mut self, stmt: &Stmt) -> StatementResult {
         if let Stmt::If {
             condition,
             then_branch,
             else_branch,
+            span: _,
         } = stmt
         {
-            let value = self.evaluate(condition).unwrap();
+            let value = match self.evaluate(condition) {
+                Ok(value) => value,
+                Err(err) => return Err(err),
+            };
+
             if self.is_truthy(&value) {
-                self.execute(&then_branch)
+                self.execute(then_branch)
             } else if let Some(else_stmt) = else_branch {
                 self.execute(else_stmt)
             } else {
@@ -465,7 +1190,12 @@ impl StmtVisitor<StatementResult> for Interpreter {
     }
 
     fn visit_while_stmt(&mut self, stmt: &Stmt) -> StatementResult {
-        if let Stmt::While { condition, body } = stmt {
+        if let Stmt::While {
+            condition,
+            body,
+            span: _,
+        } = stmt
+        {
             loop {
                 // TODO replace unwrap with match
                 let condition_result = self.evaluate(condition);
@@ -476,7 +1206,7 @@ impl StmtVisitor<StatementResult> for Interpreter {
                             break;
                         }
                         // TODO Execute should return runtime errors if it breaks
-                        let exec_result = self.execute(&body);
+                        let exec_result = self.execute(body);
 
                         match exec_result {
                             Ok(_) => (),
@@ -492,22 +1222,54 @@ impl StmtVisitor<StatementResult> for Interpreter {
         }
     }
 
+    #[allow(clippy::question_mark)]
     fn visit_function_stmt(&mut self, stmt: &Stmt) -> StatementResult {
         if let Stmt::Function {
             name,
             params: _,
             body: _,
+            decorators,
+            return_type: _,
+            span: _,
         } = stmt
         {
-            self.environment.define(
-                name,
-                &Value::Callable {
-                    callable: Callable::Function {
-                        declaration: Box::new(stmt.clone()),
-                        closure: Rc::clone(&self.environment),
-                    },
+            // Decorator expressions are evaluated top to bottom, then applied from
+            // the one closest to the function outwards
+            let mut decorator_values = vec![];
+
+            for decorator in decorators.iter() {
+                match self.evaluate(decorator) {
+                    Ok(value) => decorator_values.push(value),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            let mut function = Value::Callable {
+                callable: Callable::Function {
+                    declaration: Box::new(stmt.clone()),
+                    closure: Rc::clone(&self.environment),
                 },
-            );
+            };
+
+            for decorator in decorator_values.iter().rev() {
+                function = match decorator {
+                    Value::Callable { callable } if callable.arity() == 1 => {
+                        match callable.call(self, name, &vec![function]) {
+                            Ok(value) => value,
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    _ => {
+                        return Err(InterpreterError::RuntimeError {
+                            token: Box::new(name.clone()),
+                            error: "Decorators must be functions that take one argument."
+                                .to_owned(),
+                        })
+                    }
+                };
+            }
+
+            self.environment.define(name, &function);
 
             Ok(())
         } else {
@@ -517,7 +1279,12 @@ impl StmtVisitor<StatementResult> for Interpreter {
 
     // TODO This should return a return with an enclosing value
     fn visit_return_stmt(&mut self, stmt: &Stmt) -> StatementResult {
-        if let Stmt::Return { keyword: _, value } = stmt {
+        if let Stmt::Return {
+            keyword: _,
+            value,
+            span: _,
+        } = stmt
+        {
             // self.environment.define(
             //     name,
             //     &Value::Callable {
@@ -527,6 +1294,37 @@ impl StmtVisitor<StatementResult> for Interpreter {
             //     },
             // )
 
+            // Returning the result of a call hands the call back to Callable::call
+            // so that tail-recursive functions run in constant stack space. A
+            // pending defer has to see the call happen first, so we only do this
+            // when the current function has none. Those of its callers run later
+            // anyway.
+            let nothing_deferred = self.deferred[self.deferred_base..]
+                .iter()
+                .all(|stmts| stmts.is_empty());
+
+            if let (
+                true,
+                Expr::Call {
+                    callee,
+                    paren,
+                    arguments,
+                    optional,
+                    span: _,
+                },
+            ) = (nothing_deferred, value.as_ref())
+            {
+                return match self.prepare_call(callee, paren, arguments, *optional) {
+                    Ok(Some((callable, arguments))) => Err(InterpreterError::TailCall {
+                        callee: Box::new(Value::Callable { callable }),
+                        paren: Box::new(paren.clone()),
+                        arguments,
+                    }),
+                    Ok(None) => Err(InterpreterError::Return { value: Value::Nil }),
+                    Err(err) => Err(err),
+                };
+            }
+
             let value = self.evaluate(value);
 
             match value {
@@ -537,4 +1335,711 @@ impl StmtVisitor<StatementResult> for Interpreter {
             panic!("Nope")
         }
     }
+
+    fn visit_assign_stmt(&mut self, stmt: &Stmt) -> StatementResult {
+        if let Stmt::Assign {
+            names,
+            values,
+            span: _,
+        } = stmt
+        {
+            // Every value is evaluated before anything is assigned so that swaps
+            // like `a, b = b, a` see the old values
+            let mut evaluated = vec![];
+
+            for value in values.iter() {
+                match self.evaluate(value) {
+                    Ok(value) => evaluated.push(value),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            for (name, value) in names.iter().zip(evaluated.iter()) {
+                match self.environment.assign(name, value) {
+                    Ok(_) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            Ok(())
+        } else {
+            panic!("Nope")
+        }
+    }
+
+    fn visit_unpack_stmt(&mut self, stmt: &Stmt) -> StatementResult {
+        if let Stmt::Unpack {
+            names,
+            paren,
+            initializer,
+            constant,
+            span: _,
+        } = stmt
+        {
+            let values = match self.evaluate(initializer) {
+                Ok(Value::Tuple { values }) => values,
+                Ok(_) => {
+                    return Err(InterpreterError::RuntimeError {
+                        token: Box::new(paren.clone()),
+                        error: "Can only unpack a tuple.".to_owned(),
+                    })
+                }
+                Err(err) => return Err(err),
+            };
+
+            if values.len() != names.len() {
+                return Err(InterpreterError::RuntimeError {
+                    token: Box::new(paren.clone()),
+                    error: format!(
+                        "Expected {} values to unpack, but got {}.",
+                        names.len(),
+                        values.len()
+                    ),
+                });
+            }
+
+            for (name, value) in names.iter().zip(values.iter()) {
+                if *constant {
+                    self.environment.define_constant(name, value);
+                } else {
+                    self.environment.define(name, value);
+                }
+            }
+
+            Ok(())
+        } else {
+            panic!("Nope")
+        }
+    }
+
+    fn visit_enum_stmt(&mut self, stmt: &Stmt) -> StatementResult {
+        if let Stmt::Enum {
+            name,
+            variants,
+            span: _,
+        } = stmt
+        {
+            self.environment.define(
+                name,
+                &Value::Enum {
+                    name: name.lexeme.clone(),
+                    variants: Rc::new(variants.clone()),
+                },
+            );
+
+            Ok(())
+        } else {
+            panic!("Nope")
+        }
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) -> StatementResult {
+        if let Stmt::ForIn {
+            name,
+            iterable,
+            body,
+            span: _,
+        } = stmt
+        {
+            let (start, end, step, inclusive) = match self.evaluate(iterable) {
+                Ok(Value::Range {
+                    start,
+                    end,
+                    step,
+                    inclusive,
+                }) => (start, end, step, inclusive),
+                Ok(_) => {
+                    return Err(InterpreterError::RuntimeError {
+                        token: Box::new(name.clone()),
+                        error: "Can only iterate over a range.".to_owned(),
+                    })
+                }
+                Err(err) => return Err(err),
+            };
+
+            // Each iteration gets a fresh environment so closures capture that iteration's value
+            let mut current = start;
+
+            while Self::range_continues(end, step, inclusive, current) {
+                let environment = Environment::new(Some(Rc::clone(&self.environment)));
+                environment.define(
+                    name,
+                    &Value::Double {
+                        value: current as f64,
+                    },
+                );
+
+                match self.execute_block(std::slice::from_ref(body.as_ref()), environment) {
+                    Ok(_) => (),
+                    Err(err) => return Err(err),
+                }
+
+                // Stepping past i64 means we have also stepped past the end
+                current = match current.checked_add(step) {
+                    Some(next) => next,
+                    None => break,
+                };
+            }
+
+            Ok(())
+        } else {
+            panic!("Nope")
+        }
+    }
+
+    fn visit_defer_stmt(&mut self, stmt: &Stmt) -> StatementResult {
+        if let Stmt::Defer {
+            keyword,
+            stmt,
+            span: _,
+        } = stmt
+        {
+            match self.deferred.last_mut() {
+                Some(stmts) => {
+                    stmts.push(stmt.as_ref().clone());
+                    Ok(())
+                }
+                None => Err(InterpreterError::new_runtime_error(
+                    keyword,
+                    "Can't use 'defer' outside of a block.".to_string(),
+                )),
+            }
+        } else {
+            panic!("Nope")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        parser::Parser,
+        token::{Scanner, Span, Token, TokenType, Value},
+    };
+
+    use super::{Interpreter, InterpreterError};
+
+    fn run(source: &str) -> Interpreter {
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+        let _ = interpreter.interpret(&statements);
+        interpreter
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> Result<Value, InterpreterError> {
+        interpreter.global.get(Token {
+            ttype: TokenType::IDENTIFIER,
+            lexeme: name.to_string(),
+            literal: None,
+            line: 0,
+            column: 0,
+            span: Span::default(),
+        })
+    }
+
+    #[test]
+    fn it_evaluates_bitwise_operators() {
+        let interpreter = run("var a = 6 & 3; var b = 6 | 3; var c = 6 ^ 3; var d = ~5; var e = 1 << 4; var f = 256 >> 2;");
+
+        assert_eq!(Ok(Value::Double { value: 2.0 }), global(&interpreter, "a"));
+        assert_eq!(Ok(Value::Double { value: 7.0 }), global(&interpreter, "b"));
+        assert_eq!(Ok(Value::Double { value: 5.0 }), global(&interpreter, "c"));
+        assert_eq!(Ok(Value::Double { value: -6.0 }), global(&interpreter, "d"));
+        assert_eq!(Ok(Value::Double { value: 16.0 }), global(&interpreter, "e"));
+        assert_eq!(Ok(Value::Double { value: 64.0 }), global(&interpreter, "f"));
+    }
+
+    #[test]
+    fn it_binds_bitwise_operators_tighter_than_equality() {
+        let interpreter = run("var a = 5 & 4 == 4;");
+
+        assert_eq!(
+            Ok(Value::Boolean { value: true }),
+            global(&interpreter, "a")
+        );
+    }
+
+    #[test]
+    fn it_rejects_bitwise_operators_on_non_integers() {
+        let (tokens, _) = Scanner::new("var a = 1;\na = 1.5 & 1;").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let _ = interpreter.execute(&statements[0]);
+
+        match interpreter.execute(&statements[1]) {
+            Err(InterpreterError::RuntimeError { token, error }) => {
+                assert_eq!("&", token.lexeme);
+                assert_eq!(2, token.line);
+                assert_eq!("Operands must be integers.", error);
+            }
+            result => panic!("Expected a runtime error, got {:?}", result),
+        }
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "a"));
+    }
+
+    #[test]
+    fn it_declares_several_variables_at_once() {
+        let interpreter = run("var a = 1, b = a + 1, c;");
+
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "a"));
+        assert_eq!(Ok(Value::Double { value: 2.0 }), global(&interpreter, "b"));
+        assert_eq!(Ok(Value::Nil), global(&interpreter, "c"));
+    }
+
+    #[test]
+    fn it_evaluates_every_value_before_a_parallel_assignment() {
+        let interpreter = run("var a = 1, b = 2; a, b = b, a;");
+
+        assert_eq!(Ok(Value::Double { value: 2.0 }), global(&interpreter, "a"));
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "b"));
+    }
+
+    #[test]
+    fn it_returns_and_unpacks_tuples() {
+        let interpreter =
+            run("fun pair(a) { return (a, a + 1); } var (x, y) = pair(1); var t = (x,);");
+
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "x"));
+        assert_eq!(Ok(Value::Double { value: 2.0 }), global(&interpreter, "y"));
+        assert_eq!(
+            Ok(Value::Tuple {
+                values: vec![Value::Double { value: 1.0 }]
+            }),
+            global(&interpreter, "t")
+        );
+    }
+
+    #[test]
+    fn it_compares_tuples_structurally() {
+        let interpreter =
+            run("var a = (1, (2, \"b\")) == (1, (2, \"b\")); var b = (1, 2) == (2, 1);");
+
+        assert_eq!(
+            Ok(Value::Boolean { value: true }),
+            global(&interpreter, "a")
+        );
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "b")
+        );
+    }
+
+    #[test]
+    fn it_builds_and_inspects_enum_variants() {
+        let interpreter = run("enum Shape { Circle(r), Rect(w, h), Empty }
+            var same = Shape.Rect(1, 2) == Shape.Rect(1, 2);
+            var different = Shape.Circle(1) == Shape.Rect(1, 1);
+            var h = Shape.Rect(1, 2).h;
+            var name = variant(Shape.Empty);");
+
+        assert_eq!(
+            Ok(Value::Boolean { value: true }),
+            global(&interpreter, "same")
+        );
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "different")
+        );
+        assert_eq!(Ok(Value::Double { value: 2.0 }), global(&interpreter, "h"));
+        assert_eq!(
+            Ok(Value::String {
+                value: "Empty".to_string()
+            }),
+            global(&interpreter, "name")
+        );
+    }
+
+    #[test]
+    fn it_coalesces_nil_values() {
+        let interpreter = run("var a = nil ?? 1; var b = false ?? 1; var c = nil ?? nil;");
+
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "a"));
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "b")
+        );
+        assert_eq!(Ok(Value::Nil), global(&interpreter, "c"));
+    }
+
+    #[test]
+    fn it_skips_a_nil_safe_call_on_nil() {
+        let interpreter = run("var calls = 0;
+            fun count() { calls = calls + 1; return calls; }
+            var f;
+            var a = f?.(count());
+            var b = count?.();");
+
+        assert_eq!(Ok(Value::Nil), global(&interpreter, "a"));
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "b"));
+        assert_eq!(
+            Ok(Value::Double { value: 1.0 }),
+            global(&interpreter, "calls")
+        );
+    }
+
+    #[test]
+    fn it_indexes_and_slices_strings_by_character() {
+        let interpreter = run("var s = \"héllo\";
+            var a = s[1]; var b = s[-1]; var c = s[1:3]; var d = s[:2]; var e = s[-2:];");
+
+        for (name, expected) in [
+            ("a", "é"),
+            ("b", "o"),
+            ("c", "él"),
+            ("d", "hé"),
+            ("e", "lo"),
+        ] {
+            assert_eq!(
+                Ok(Value::String {
+                    value: expected.to_string()
+                }),
+                global(&interpreter, name)
+            );
+        }
+    }
+
+    #[test]
+    fn it_reports_the_line_of_an_out_of_range_index() {
+        let (tokens, _) = Scanner::new("var s = \"abc\";\ns[3];").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let _ = interpreter.execute(&statements[0]);
+
+        assert_eq!(
+            Err(InterpreterError::RuntimeError {
+                token: Box::new(Token {
+                    ttype: TokenType::LEFT_BRACKET,
+                    lexeme: "[".to_string(),
+                    literal: None,
+                    line: 2,
+                    column: 2,
+                    span: Span::new(16, 17),
+                }),
+                error: "String index 3 out of range for length 3.".to_string()
+            }),
+            interpreter.execute(&statements[1])
+        );
+    }
+
+    #[test]
+    fn it_pipes_values_into_calls() {
+        let interpreter = run("fun double(x) { return x * 2; }
+            fun add(a, b) { return a + b; }
+            var a = 3 |> double |> add(1);");
+
+        assert_eq!(Ok(Value::Double { value: 7.0 }), global(&interpreter, "a"));
+    }
+
+    #[test]
+    fn it_applies_decorators_bottom_up() {
+        let interpreter = run(
+            "fun add(n) { fun decorator(f) { fun wrapper(x) { return f(x) + n; } return wrapper; } return decorator; }
+            fun double(f) { fun wrapper(x) { return f(x) * 2; } return wrapper; }
+            @double
+            @add(1)
+            fun identity(x) { return x; }
+            var a = identity(3);",
+        );
+
+        assert_eq!(Ok(Value::Double { value: 8.0 }), global(&interpreter, "a"));
+    }
+
+    #[test]
+    fn it_iterates_over_ranges() {
+        let interpreter = run(
+            "var exclusive = 0; for (var i in 0..4) exclusive = exclusive + i;
+            var inclusive = 0; for (var i in 0..=4) inclusive = inclusive + i;
+            var stepped = 0; for (var i in 10..0 |> step(-5)) stepped = stepped + i;",
+        );
+
+        assert_eq!(
+            Ok(Value::Double { value: 6.0 }),
+            global(&interpreter, "exclusive")
+        );
+        assert_eq!(
+            Ok(Value::Double { value: 10.0 }),
+            global(&interpreter, "inclusive")
+        );
+        assert_eq!(
+            Ok(Value::Double { value: 15.0 }),
+            global(&interpreter, "stepped")
+        );
+    }
+
+    #[test]
+    fn it_stops_ranges_at_the_edge_of_i64() {
+        let interpreter = run("var big = 9000000000000000000;
+            var count = 0; for (var i in big..=9200000000000000000 |> step(300000000000000000)) count = count + 1;
+            var wide = big in -big..=big;");
+
+        assert_eq!(
+            Ok(Value::Double { value: 1.0 }),
+            global(&interpreter, "count")
+        );
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "wide")
+        );
+    }
+
+    #[test]
+    fn it_tests_range_membership_and_slices_with_ranges() {
+        let interpreter = run("var r = 0..10 |> step(3);
+            var a = 9 in r; var b = 10 in r; var c = 1.5 in 0..3;
+            var s = \"hello\"[1..=3];");
+
+        assert_eq!(
+            Ok(Value::Boolean { value: true }),
+            global(&interpreter, "a")
+        );
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "b")
+        );
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "c")
+        );
+        assert_eq!(
+            Ok(Value::String {
+                value: "ell".to_string()
+            }),
+            global(&interpreter, "s")
+        );
+    }
+
+    #[test]
+    fn it_runs_tail_recursion_in_constant_stack_space() {
+        let interpreter = run("fun count(n, acc) { if (n == 0) return acc; return count(n - 1, acc + 1); }\nvar a = count(100000, 0);");
+
+        assert_eq!(
+            Ok(Value::Double { value: 100000.0 }),
+            global(&interpreter, "a")
+        );
+    }
+
+    #[test]
+    fn it_runs_mutual_tail_recursion() {
+        let interpreter = run("fun even(n) { if (n == 0) return true; return odd(n - 1); }\nfun odd(n) { if (n == 0) return false; return even(n - 1); }\nvar a = even(100001);");
+
+        assert_eq!(
+            Ok(Value::Boolean { value: false }),
+            global(&interpreter, "a")
+        );
+    }
+
+    #[test]
+    fn it_runs_deferred_statements_in_reverse_order() {
+        let interpreter = run("var log = \"\";\nfun f() { defer log = log + \"a\"; defer log = log + \"b\"; log = log + \"c\"; return log; }\nvar r = f();");
+
+        assert_eq!(
+            Ok(Value::String {
+                value: "c".to_string()
+            }),
+            global(&interpreter, "r")
+        );
+        assert_eq!(
+            Ok(Value::String {
+                value: "cba".to_string()
+            }),
+            global(&interpreter, "log")
+        );
+    }
+
+    #[test]
+    fn it_runs_deferred_statements_after_a_returned_call() {
+        let interpreter = run("var log = \"\";\nfun g() { log = log + \"g\"; }\nfun f() { defer log = log + \"f\"; return g(); }\nf();");
+
+        assert_eq!(
+            Ok(Value::String {
+                value: "gf".to_string()
+            }),
+            global(&interpreter, "log")
+        );
+    }
+
+    #[test]
+    fn it_keeps_tail_calls_under_a_deferring_caller() {
+        let interpreter = run("var log = \"\";\nfun loop(n) { if (n == 0) return 0; return loop(n - 1); }\nfun main() { defer log = log + \"done\"; return loop(100000); }\nvar r = main();");
+
+        assert_eq!(Ok(Value::Double { value: 0.0 }), global(&interpreter, "r"));
+        assert_eq!(
+            Ok(Value::String {
+                value: "done".to_string()
+            }),
+            global(&interpreter, "log")
+        );
+    }
+
+    #[test]
+    fn it_runs_deferred_statements_on_runtime_errors() {
+        let interpreter =
+            run("var log = \"\";\n{ defer log = log + \"done\"; var bad = 1 + nil; }");
+
+        assert_eq!(
+            Ok(Value::String {
+                value: "done".to_string()
+            }),
+            global(&interpreter, "log")
+        );
+    }
+
+    #[test]
+    fn it_evaluates_if_expressions() {
+        let interpreter = run("var a = if (true) { 1 } else { 2 };\nvar b = if (false) { 1 } else if (true) { 3 } else { 4 };\nvar c = if (false) { 1 };");
+
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "a"));
+        assert_eq!(Ok(Value::Double { value: 3.0 }), global(&interpreter, "b"));
+        assert_eq!(Ok(Value::Nil), global(&interpreter, "c"));
+    }
+
+    #[test]
+    fn it_evaluates_block_expressions() {
+        let interpreter = run("var x = 10;\nvar a = { var y = 2; x * y };\nvar b = { var x = 1; x };\nvar c = { if (true) { x = 5; } if (false) print x; x + 1 };\nvar d = { x = 7; };");
+
+        assert_eq!(Ok(Value::Double { value: 20.0 }), global(&interpreter, "a"));
+        assert_eq!(Ok(Value::Double { value: 1.0 }), global(&interpreter, "b"));
+        assert_eq!(Ok(Value::Double { value: 6.0 }), global(&interpreter, "c"));
+        assert_eq!(Ok(Value::Nil), global(&interpreter, "d"));
+        assert_eq!(Ok(Value::Double { value: 7.0 }), global(&interpreter, "x"));
+    }
+
+    #[test]
+    fn it_reports_runtime_errors_at_the_offending_token() {
+        let (tokens, _) = Scanner::new("var a = 1;\nvar b = a +\n  nil;\nundefined;").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let _ = interpreter.execute(&statements[0]);
+
+        match interpreter.execute(&statements[1]) {
+            Err(InterpreterError::RuntimeError { token, error: _ }) => {
+                assert_eq!("+", token.lexeme);
+                assert_eq!(2, token.line);
+            }
+            result => panic!("Expected a runtime error, got {:?}", result),
+        }
+        match interpreter.execute(&statements[2]) {
+            Err(InterpreterError::RuntimeError { token, error }) => {
+                assert_eq!("undefined", token.lexeme);
+                assert_eq!(4, token.line);
+                assert_eq!("Undefined variable 'undefined'", error);
+            }
+            result => panic!("Expected a runtime error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn it_reports_runtime_errors_in_if_conditions_at_the_offending_token() {
+        let (tokens, _) = Scanner::new("var x = 1;\nif (x +\n  \"a\" > 1) print 1;").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let _ = interpreter.execute(&statements[0]);
+
+        match interpreter.execute(&statements[1]) {
+            Err(InterpreterError::RuntimeError { token, error: _ }) => {
+                assert_eq!("+", token.lexeme);
+                assert_eq!(2, token.line);
+            }
+            result => panic!("Expected a runtime error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn it_captures_a_traceback_for_uncaught_errors() {
+        let (tokens, _) = Scanner::new(
+            "fun inner() {\n  return nil + 1;\n}\nfun outer() {\n  inner();\n  return 1;\n}\nfun tail() {\n  return outer();\n}\nclock();\ntail();",
+        )
+        .scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let mut error_line = 0;
+        for statement in statements.iter() {
+            if let Err(InterpreterError::RuntimeError { token, error: _ }) =
+                interpreter.execute(statement)
+            {
+                error_line = token.line;
+                break;
+            }
+        }
+
+        // `tail` hands its frame over to `outer`, and the earlier call to the
+        // native has already returned
+        assert_eq!(
+            vec![
+                "at inner (line 2)".to_string(),
+                "at outer (line 5)".to_string(),
+                "at <script> (line 12)".to_string(),
+            ],
+            interpreter.traceback(error_line)
+        );
+    }
+
+    #[test]
+    fn it_shows_native_calls_in_the_traceback() {
+        let (tokens, _) = Scanner::new("fun f() {\n  return step(1, 2);\n}\nf();").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let mut error_line = 0;
+        for statement in statements.iter() {
+            if let Err(InterpreterError::RuntimeError { token, error: _ }) =
+                interpreter.execute(statement)
+            {
+                error_line = token.line;
+                break;
+            }
+        }
+
+        assert_eq!(
+            vec![
+                "at [native step]".to_string(),
+                "at f (line 2)".to_string(),
+                "at <script> (line 4)".to_string(),
+            ],
+            interpreter.traceback(error_line)
+        );
+    }
+
+    #[test]
+    fn it_returns_uncaught_errors_as_a_diagnostic_with_the_traceback() {
+        let (tokens, _) = Scanner::new("fun f() {\n  return -\"a\";\n}\nprint f();").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+
+        let diagnostic = Interpreter::new().interpret(&statements).unwrap_err();
+
+        assert_eq!("Operand must be a number.", diagnostic.message);
+        assert_eq!(Span::new(19, 20), diagnostic.span);
+        assert_eq!(
+            vec!["traceback, most recent call first:\nat f (line 2)\nat <script> (line 4)"],
+            diagnostic.notes
+        );
+    }
+
+    #[test]
+    fn it_reports_the_expected_and_actual_argument_counts() {
+        let (tokens, _) =
+            Scanner::new("fun add(a, b) { return a + b; }\nadd(1);\nclock(2);").scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        let mut interpreter = Interpreter::new();
+
+        let _ = interpreter.execute(&statements[0]);
+
+        for (statement, expected) in [
+            (&statements[1], "Expected 2 arguments to 'add' but got 1."),
+            (&statements[2], "Expected 0 arguments to 'clock' but got 1."),
+        ] {
+            match interpreter.execute(statement) {
+                Err(InterpreterError::RuntimeError { token: _, error }) => {
+                    assert_eq!(expected, error)
+                }
+                result => panic!("Expected a runtime error, got {:?}", result),
+            }
+        }
+    }
 }
@@ -1,31 +1,145 @@
 use std::{
     env, fs,
     io::{self, Write},
+    process,
 };
 
-use crate::interpreter::Interpreter;
+use crate::{
+    checker::TypeChecker,
+    diagnostic::{Diagnostic, ErrorFormat, Severity, INPUT_ERROR, USAGE_ERROR},
+    expression::Stmt,
+    interpreter::Interpreter,
+    resolver::{Resolver, Warning},
+    token::{Comment, Span},
+};
 
+mod checker;
+mod diagnostic;
 mod environment;
 mod expression;
 mod interpreter;
 mod parser;
+mod resolver;
 mod token;
 
-static mut HAD_ERROR: bool = false;
+// Why a script didn't run to completion. Each maps to a sysexits(3) code so
+// that whoever ran us can tell what kind of problem it was.
+#[derive(Debug, PartialEq)]
+enum Failure {
+    Usage,
+    NoInput,
+    Compile,
+    Runtime,
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Usage => 64,
+            Failure::Compile => 65,
+            Failure::NoInput => 66,
+            Failure::Runtime => 70,
+        }
+    }
+}
+
+const USAGE: &str = "Usage: rustlox [--deny-warnings] [--error-format=human|json] [check] [script]";
+
+// What was asked for on the command line
+struct Options {
+    check: bool,
+    script: Option<String>,
+    // Treat warnings as errors, so that they stop the script from running
+    deny_warnings: bool,
+    error_format: ErrorFormat,
+}
+
+// Arguments that don't make sense, along with how to report that
+struct UsageError {
+    message: String,
+    error_format: ErrorFormat,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Options, UsageError> {
+        let mut options = Options {
+            check: false,
+            script: None,
+            deny_warnings: false,
+            error_format: ErrorFormat::Human,
+        };
+        let mut positional = vec![];
+        let mut unknown = None;
+
+        // Keep going past an unknown flag so that a later --error-format still
+        // decides how it is reported
+        for arg in args {
+            match arg.as_str() {
+                "--deny-warnings" => options.deny_warnings = true,
+                "--error-format=human" => options.error_format = ErrorFormat::Human,
+                "--error-format=json" => options.error_format = ErrorFormat::Json,
+                _ if arg.starts_with("--") => {
+                    unknown.get_or_insert(arg);
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        if let Some(flag) = unknown {
+            return Err(UsageError {
+                message: format!("Unknown option '{}'.", flag),
+                error_format: options.error_format,
+            });
+        }
+
+        match positional.as_slice() {
+            [] => (),
+            [script] => options.script = Some(script.clone()),
+            [command, script] if command == "check" => {
+                options.check = true;
+                options.script = Some(script.clone());
+            }
+            _ => {
+                return Err(UsageError {
+                    message: "Too many arguments.".to_string(),
+                    error_format: options.error_format,
+                })
+            }
+        }
+
+        Ok(options)
+    }
+}
 
 fn main() {
     env_logger::init();
 
-    if env::args().len() > 2 {
-        println!("Usage: rustlox [script]");
-    } else if env::args().len() == 2 {
-        let path = env::args().nth(1).unwrap();
-        run(&fs::read_to_string(path).unwrap());
-        if unsafe { HAD_ERROR } {
-            return;
+    let result = match Options::parse(env::args().skip(1)) {
+        Ok(options) => match &options.script {
+            Some(path) if options.check => {
+                read_script(path, &options).and_then(|source| check(&source, path, &options))
+            }
+            Some(path) => {
+                read_script(path, &options).and_then(|source| run(&source, path, &options))
+            }
+            None => {
+                prompt(&options);
+                Ok(())
+            }
+        },
+        Err(error) => {
+            fail(
+                Diagnostic::error(USAGE_ERROR, error.message, Span::default())
+                    .with_help(USAGE.to_string()),
+                "<command line>",
+                error.error_format,
+            );
+            Err(Failure::Usage)
         }
-    } else {
-        prompt();
+    };
+
+    if let Err(failure) = result {
+        process::exit(failure.exit_code());
     }
 
     // let expr = Expr::Binary {
@@ -54,7 +168,7 @@ fn main() {
     // };
 }
 
-fn prompt() {
+fn prompt(options: &Options) {
     loop {
         print!("> ");
         let _ = io::stdout().flush();
@@ -65,24 +179,162 @@ fn prompt() {
             break;
         }
 
-        run(&input);
-        unsafe { HAD_ERROR = false };
+        // Errors have already been reported, and the session carries on regardless
+        let _ = run(&input, "<stdin>", options);
+    }
+}
+
+fn read_script(path: &str, options: &Options) -> Result<String, Failure> {
+    match fs::read_to_string(path) {
+        Ok(source) => Ok(source),
+        Err(err) => {
+            fail(
+                Diagnostic::error(
+                    INPUT_ERROR,
+                    format!("Could not read '{}': {}", path, err),
+                    Span::default(),
+                ),
+                path,
+                options.error_format,
+            );
+            Err(Failure::NoInput)
+        }
+    }
+}
+
+// Reports a problem that has no place in a script to point at. People get the
+// message and any help as plain lines, tools get the usual JSON.
+fn fail(diagnostic: Diagnostic, path: &str, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Human => {
+            eprintln!("{}", diagnostic.message);
+            for help in diagnostic.help.iter() {
+                eprintln!("{}", help);
+            }
+        }
+        ErrorFormat::Json => diagnostic.emit("", path, format),
     }
 }
 
-fn run(source: &str) {
+fn run(source: &str, path: &str, options: &Options) -> Result<(), Failure> {
+    let statements = compile(source, path, options)?;
+
+    // println!("{}", AstPrinter {}.print(&expression));
+    match Interpreter::new().interpret(&statements) {
+        Ok(_) => Ok(()),
+        Err(diagnostic) => {
+            diagnostic.emit(source, path, options.error_format);
+            Err(Failure::Runtime)
+        }
+    }
+}
+
+// Runs every static pass over the script without executing it
+fn check(source: &str, path: &str, options: &Options) -> Result<(), Failure> {
+    let statements = compile(source, path, options)?;
+
+    let errors = TypeChecker::new().check(&statements);
+    if report(
+        errors.iter().map(|error| error.diagnostic()),
+        source,
+        path,
+        options,
+    ) {
+        return Err(Failure::Compile);
+    }
+
+    Ok(())
+}
+
+// Scans, parses and resolves the script, reporting whatever each stage finds.
+// We stop after the first stage with errors, since later ones would only trip
+// over the same mistakes.
+fn compile(source: &str, path: &str, options: &Options) -> Result<Vec<Stmt>, Failure> {
     let mut scanner = token::Scanner::new(source);
-    let tokens = scanner.scan_tokens();
+    let (tokens, errors) = scanner.scan_tokens();
+    if report(
+        errors.iter().map(|error| error.diagnostic()),
+        source,
+        path,
+        options,
+    ) {
+        return Err(Failure::Compile);
+    }
 
     // for token in tokens.clone().into_iter() {
     //     println!("{}", token);
     // }
 
     let mut parser = parser::Parser::new(&tokens);
-    let statements = parser.parse();
+    let (statements, errors) = parser.parse();
+    if report(
+        errors.iter().map(|error| error.diagnostic()),
+        source,
+        path,
+        options,
+    ) {
+        return Err(Failure::Compile);
+    }
 
-    // println!("{}", AstPrinter {}.print(&expression));
-    Interpreter::new().interpret(&statements);
+    let (errors, warnings) = Resolver::new().resolve(&statements);
+    let denied = warn(&warnings, scanner.comments(), source, path, options);
+    if report(
+        errors.iter().map(|error| error.diagnostic()),
+        source,
+        path,
+        options,
+    ) || denied
+    {
+        return Err(Failure::Compile);
+    }
+
+    Ok(statements)
+}
+
+// Prints the warnings that the source doesn't allow, as errors if warnings are
+// denied. Returns whether any were denied.
+fn warn(
+    warnings: &[Warning],
+    comments: &[Comment],
+    source: &str,
+    path: &str,
+    options: &Options,
+) -> bool {
+    let diagnostics = warnings
+        .iter()
+        .filter(|warning| !warning.is_allowed(source, comments))
+        .map(|warning| {
+            let diagnostic = warning.diagnostic();
+
+            if options.deny_warnings {
+                Diagnostic {
+                    severity: Severity::Error,
+                    ..diagnostic
+                }
+                .with_note("warnings are errors because of --deny-warnings".to_string())
+            } else {
+                diagnostic
+            }
+        });
+
+    report(diagnostics, source, path, options) && options.deny_warnings
+}
+
+// Prints each diagnostic, returning whether there were any
+fn report(
+    diagnostics: impl Iterator<Item = Diagnostic>,
+    source: &str,
+    path: &str,
+    options: &Options,
+) -> bool {
+    let mut had_error = false;
+
+    for diagnostic in diagnostics {
+        diagnostic.emit(source, path, options.error_format);
+        had_error = true;
+    }
+
+    had_error
 }
 
 // fn error(line_number: i32, message: &str) {
@@ -91,5 +343,125 @@ fn run(source: &str) {
 
 // fn report(line_number: i32, location: &str, message: &str) {
 //     println!("[line {}] Error{}: {}", line_number, location, message);
-//     unsafe { HAD_ERROR = true };
 // }
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::ErrorFormat;
+
+    use super::{check, run, Failure, Options, UsageError};
+
+    fn parse(args: &[&str]) -> Result<Options, UsageError> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn it_accepts_flags_before_and_after_the_script() {
+        for args in [
+            vec!["--deny-warnings", "--error-format=json", "a.lox"],
+            vec!["a.lox", "--error-format=json", "--deny-warnings"],
+        ] {
+            match parse(&args) {
+                Ok(options) => {
+                    assert_eq!(Some("a.lox".to_string()), options.script);
+                    assert!(options.deny_warnings);
+                    assert_eq!(ErrorFormat::Json, options.error_format);
+                    assert!(!options.check);
+                }
+                Err(error) => panic!("Expected options, got '{}'", error.message),
+            }
+        }
+    }
+
+    #[test]
+    fn it_checks_a_script_with_flags_in_between() {
+        match parse(&["check", "--deny-warnings", "a.lox"]) {
+            Ok(options) => {
+                assert!(options.check);
+                assert_eq!(Some("a.lox".to_string()), options.script);
+            }
+            Err(error) => panic!("Expected options, got '{}'", error.message),
+        }
+
+        assert!(parse(&["a.lox", "check"]).is_err());
+        assert!(parse(&["check", "a.lox", "b.lox"]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_unknown_flags_in_the_requested_format() {
+        match parse(&["--verbose", "a.lox", "--error-format=json"]) {
+            Ok(_) => panic!("Expected an unknown option to be rejected"),
+            Err(error) => {
+                assert_eq!("Unknown option '--verbose'.", error.message);
+                assert_eq!(ErrorFormat::Json, error.error_format);
+            }
+        }
+
+        assert!(parse(&["--error-format=xml"]).is_err());
+    }
+
+    #[test]
+    fn it_starts_a_prompt_without_a_script() {
+        match parse(&[]) {
+            Ok(options) => {
+                assert_eq!(None, options.script);
+                assert_eq!(ErrorFormat::Human, options.error_format);
+            }
+            Err(error) => panic!("Expected options, got '{}'", error.message),
+        }
+    }
+
+    #[test]
+    fn it_maps_failures_to_sysexits_codes() {
+        assert_eq!(64, Failure::Usage.exit_code());
+        assert_eq!(65, Failure::Compile.exit_code());
+        assert_eq!(66, Failure::NoInput.exit_code());
+        assert_eq!(70, Failure::Runtime.exit_code());
+    }
+
+    #[test]
+    fn it_fails_to_compile_on_errors_from_any_static_pass() {
+        let options = parse(&["--error-format=json"]).ok().unwrap();
+
+        // A scan error, a parse error and a resolve error
+        for source in ["print @;", "print (1;", "const a = 1;\na = 2;"] {
+            assert_eq!(Err(Failure::Compile), run(source, "test.lox", &options));
+            assert_eq!(Err(Failure::Compile), check(source, "test.lox", &options));
+        }
+
+        // Only `check` runs the type checker
+        let source = "var a: Number = \"a\";";
+        assert_eq!(Ok(()), run(source, "test.lox", &options));
+        assert_eq!(Err(Failure::Compile), check(source, "test.lox", &options));
+    }
+
+    #[test]
+    fn it_fails_to_compile_on_denied_warnings() {
+        let source = "if (true) print 1;";
+
+        let options = parse(&["--error-format=json"]).ok().unwrap();
+        assert_eq!(Ok(()), run(source, "test.lox", &options));
+
+        let options = parse(&["--error-format=json", "--deny-warnings"])
+            .ok()
+            .unwrap();
+        assert_eq!(Err(Failure::Compile), run(source, "test.lox", &options));
+    }
+
+    #[test]
+    fn it_fails_at_runtime_after_compiling_cleanly() {
+        let options = parse(&["--error-format=json"]).ok().unwrap();
+
+        let source = "var s = \"abc\";\nprint s[3];";
+        assert_eq!(Err(Failure::Runtime), run(source, "test.lox", &options));
+        assert_eq!(Ok(()), check(source, "test.lox", &options));
+    }
+
+    #[test]
+    fn it_fails_at_runtime_on_an_error_in_an_if_condition() {
+        let options = parse(&["--error-format=json"]).ok().unwrap();
+
+        let source = "var x = 1;\nif (x + \"a\" > 1) print 1;";
+        assert_eq!(Err(Failure::Runtime), run(source, "test.lox", &options));
+    }
+}
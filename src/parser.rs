@@ -1,79 +1,94 @@
 use crate::{
-    expression::{Expr, Stmt},
-    token::{Token, TokenType, Value},
+    diagnostic::{Diagnostic, PARSE_ERROR},
+    expression::{EnumVariant, Expr, Param, Stmt},
+    token::{Span, Token, TokenType, Value},
 };
 
 #[derive(Debug)]
 pub(crate) struct Parser<'a> {
     current: i64,
     tokens: &'a Vec<Token>,
+    errors: Vec<ParseError>,
 }
 
-#[derive(Debug)]
-struct ParseError {
-    message: String,
-    #[allow(dead_code)]
-    line: i64,
-    #[allow(dead_code)]
-    error_where: String,
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ParseError {
+    pub message: String,
+    pub line: i64,
+    pub error_where: String,
+    pub span: Span,
 }
 
 impl ParseError {
-    // fn report(&self) {
-    //     println!(
-    //         "[line {}] Error{}: {}",
-    //         self.line, self.error_where, self.message
-    //     );
-    // }
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(PARSE_ERROR, self.message.clone(), self.span)
+            .with_label(self.error_where.trim().to_string())
+    }
 }
 
 impl Parser<'_> {
     pub fn new<'a>(tokens: &'a Vec<Token>) -> Parser<'a> {
-        Parser { current: 0, tokens }
+        Parser {
+            current: 0,
+            tokens,
+            errors: vec![],
+        }
     }
 
-    pub fn parse(&mut self) -> Vec<Stmt> {
+    // Parses the whole program. A syntax error doesn't stop the parse: it is
+    // recorded and we skip ahead to the next statement, so that every error in
+    // the source can be reported in one go.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<ParseError>) {
         let mut statements = vec![];
 
         while !self.is_at_end() {
-            let declaration = self.declaration();
-            // println!("{:?}", declaration);
-            statements.push(declaration);
+            let declarations = self.declaration();
+            statements.extend(declarations);
         }
 
-        statements
+        (statements, self.errors.clone())
     }
 
-    fn declaration(&mut self) -> Stmt {
-        if self.is_match(vec![TokenType::FUN]) {
-            self.function("function".to_string())
+    // A single `var` can declare several names, so this yields every statement it produced
+    fn declaration(&mut self) -> Vec<Stmt> {
+        let result = if self.is_match(vec![TokenType::FUN]) {
+            self.function("function").map(|function| vec![function])
         } else if self.is_match(vec![TokenType::VAR]) {
-            self.var_declaration()
+            self.var_declaration(false)
+        } else if self.is_match(vec![TokenType::CONST]) {
+            self.var_declaration(true)
+        } else if self.is_match(vec![TokenType::ENUM]) {
+            self.enum_declaration().map(|enum_stmt| vec![enum_stmt])
+        } else if self.is_match(vec![TokenType::AT]) {
+            self.decorated_function().map(|function| vec![function])
         } else {
-            self.statement()
-        }
-        // Call syncronize to recover from errors
-    }
+            self.statement().map(|stmt| vec![stmt])
+        };
 
-    fn function(&mut self, _kind: String) -> Stmt {
-        // TODO String interpolation
-        let name;
-        match self.consume(TokenType::IDENTIFIER, "Expect + kind + name.".to_owned()) {
-            Ok(name_token) => name = name_token,
+        match result {
+            Ok(statements) => statements,
             Err(err) => {
-                panic!("Panicked parsing function statement {}", err.message)
+                self.errors.push(err);
+                self.synchronize();
+                vec![]
             }
         }
+    }
+
+    #[allow(clippy::question_mark)]
+    fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+        let name = match self.consume(TokenType::IDENTIFIER, format!("Expect {} name.", kind)) {
+            Ok(name_token) => name_token,
+            Err(err) => return Err(err),
+        };
 
-        // TODO String interpolation
         match self.consume(
             TokenType::LEFT_PAREN,
-            "Expect '(' after + kind + name.".to_owned(),
+            format!("Expect '(' after {} name.", kind),
         ) {
             Ok(_) => (),
-            Err(err) => {
-                panic!("Panicked parsing function statement {}", err.message)
-            }
+            Err(err) => return Err(err),
         }
 
         let mut parameters = vec![];
@@ -81,15 +96,30 @@ impl Parser<'_> {
         if !self.check(TokenType::RIGHT_PAREN) {
             loop {
                 if parameters.len() >= 255 {
-                    self.error(
+                    // Too many parameters doesn't confuse the parser, so just report it
+                    let err = self.error(
                         self.peek(),
                         "Can't have more than 255 parameters.".to_string(),
                     );
+                    self.errors.push(err);
                 }
 
+                let constant = self.is_match(vec![TokenType::CONST]);
+
                 match self.consume(TokenType::IDENTIFIER, "Expect parameter name.".to_string()) {
-                    Ok(parameter) => parameters.push(parameter),
-                    Err(err) => panic!("Error parsing params {:?}", err),
+                    Ok(parameter) => {
+                        let type_annotation = match self.type_annotation() {
+                            Ok(type_annotation) => type_annotation,
+                            Err(err) => return Err(err),
+                        };
+
+                        parameters.push(Param {
+                            name: parameter,
+                            constant,
+                            type_annotation,
+                        })
+                    }
+                    Err(err) => return Err(err),
                 }
 
                 if !self.is_match(vec![TokenType::COMMA]) {
@@ -98,85 +128,277 @@ impl Parser<'_> {
             }
         }
 
-        // TODO String interpolation
         match self.consume(
             TokenType::RIGHT_PAREN,
             "Expect ')' after parameters.".to_owned(),
         ) {
             Ok(_) => (),
-            Err(err) => {
-                panic!("Panicked parsing function statement {}", err.message)
-            }
+            Err(err) => return Err(err),
         }
 
-        // TODO String interpolation
+        let return_type = match self.type_annotation() {
+            Ok(return_type) => return_type,
+            Err(err) => return Err(err),
+        };
+
         match self.consume(
             TokenType::LEFT_BRACE,
-            "Expect '{' before + kind + body.".to_owned(),
+            format!("Expect '{{' before {} body.", kind),
         ) {
             Ok(_) => (),
-            Err(err) => {
-                panic!("Panicked parsing function statement {}", err.message)
-            }
+            Err(err) => return Err(err),
         }
 
-        let body = self.block();
+        let body = match self.block() {
+            Ok(body) => body,
+            Err(err) => return Err(err),
+        };
 
-        Stmt::Function {
+        Ok(Stmt::Function {
             name,
             params: parameters,
             body,
+            decorators: vec![],
+            return_type,
+            span: self.span_from(start),
+        })
+    }
+
+    // Parses one or more `@decorator` lines (the first `@` has already been
+    // matched) followed by the function they decorate
+    fn decorated_function(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+        let mut decorators = vec![];
+
+        loop {
+            match self.call() {
+                Ok(decorator) => decorators.push(decorator),
+                Err(err) => return Err(err),
+            }
+
+            if !self.is_match(vec![TokenType::AT]) {
+                break;
+            }
         }
+
+        match self.consume(
+            TokenType::FUN,
+            "Expect function declaration after decorator.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
+
+        let mut function = self.function("function")?;
+
+        if let Stmt::Function {
+            decorators: function_decorators,
+            span,
+            ..
+        } = &mut function
+        {
+            *function_decorators = decorators;
+            *span = span.to(start);
+        }
+
+        Ok(function)
     }
 
-    fn var_declaration(&mut self) -> Stmt {
-        let consume_result = self.consume(TokenType::IDENTIFIER, "Expect variable name".to_owned());
+    fn enum_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+        let name = self.consume(TokenType::IDENTIFIER, "Expect enum name.".to_owned())?;
 
-        match consume_result {
-            Ok(token) => {
-                if self.is_match(vec![TokenType::EQUAL]) {
-                    let initializer_result = self.expression();
+        match self.consume(
+            TokenType::LEFT_BRACE,
+            "Expect '{' before enum body.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
 
-                    match initializer_result {
-                        Ok(initializer) => {
-                            let semicolon_result = self.consume(
-                                TokenType::SEMICOLON,
-                                "Expect ';' after value.".to_owned(),
-                            );
+        let mut variants = vec![];
 
-                            match semicolon_result {
-                                Ok(_) => (),
-                                Err(err) => {
-                                    panic!("Panicked parsing expression statement {}", err.message)
-                                }
-                            }
+        while !self.check(TokenType::RIGHT_BRACE) && !self.is_at_end() {
+            let variant_name =
+                self.consume(TokenType::IDENTIFIER, "Expect variant name.".to_owned())?;
 
-                            Stmt::Var {
-                                name: token,
-                                initializer: Some(initializer),
-                            }
+            let mut fields = None;
+
+            if self.is_match(vec![TokenType::LEFT_PAREN]) {
+                let mut field_names = vec![];
+
+                if !self.check(TokenType::RIGHT_PAREN) {
+                    loop {
+                        match self.consume(TokenType::IDENTIFIER, "Expect field name.".to_owned()) {
+                            Ok(field) => field_names.push(field),
+                            Err(err) => return Err(err),
                         }
-                        Err(_) => panic!("FUCCBARR"),
-                    }
-                } else {
-                    match self.consume(TokenType::SEMICOLON, "Expect ';' after value.".to_owned()) {
-                        Ok(_) => (),
-                        Err(err) => {
-                            panic!("Panicked parsing expression statement {}", err.message)
+
+                        if !self.is_match(vec![TokenType::COMMA]) {
+                            break;
                         }
                     }
+                }
 
-                    Stmt::Var {
-                        name: token,
-                        initializer: None,
-                    }
+                match self.consume(
+                    TokenType::RIGHT_PAREN,
+                    "Expect ')' after fields.".to_owned(),
+                ) {
+                    Ok(_) => (),
+                    Err(err) => return Err(err),
+                }
+
+                fields = Some(field_names);
+            }
+
+            variants.push(EnumVariant {
+                name: variant_name,
+                fields,
+            });
+
+            if !self.is_match(vec![TokenType::COMMA]) {
+                break;
+            }
+        }
+
+        match self.consume(
+            TokenType::RIGHT_BRACE,
+            "Expect '}' after enum body.".to_owned(),
+        ) {
+            Ok(_) => Ok(Stmt::Enum {
+                name,
+                variants,
+                span: self.span_from(start),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    #[allow(clippy::question_mark)]
+    fn var_declaration(&mut self, constant: bool) -> Result<Vec<Stmt>, ParseError> {
+        let keyword = self.previous().span;
+        let mut declarations = vec![];
+
+        loop {
+            // The first declaration also covers the `var`, the others start at their name
+            let start = if declarations.is_empty() {
+                keyword
+            } else {
+                self.peek().span
+            };
+
+            if self.is_match(vec![TokenType::LEFT_PAREN]) {
+                match self.unpack_declaration(constant, start) {
+                    Ok(unpack) => declarations.push(unpack),
+                    Err(err) => return Err(err),
+                }
+
+                if !self.is_match(vec![TokenType::COMMA]) {
+                    break;
+                }
+                continue;
+            }
+
+            let name = match self.consume(TokenType::IDENTIFIER, "Expect variable name.".to_owned())
+            {
+                Ok(token) => token,
+                Err(err) => return Err(err),
+            };
+
+            let type_annotation = match self.type_annotation() {
+                Ok(type_annotation) => type_annotation,
+                Err(err) => return Err(err),
+            };
+
+            let initializer = if self.is_match(vec![TokenType::EQUAL]) {
+                match self.expression() {
+                    Ok(initializer) => Some(initializer),
+                    Err(err) => return Err(err),
                 }
+            } else if constant {
+                return Err(self.current_error("Expect '=' after constant name.".to_owned()));
+            } else {
+                None
+            };
+
+            declarations.push(Stmt::Var {
+                name,
+                initializer,
+                constant,
+                type_annotation,
+                span: self.span_from(start),
+            });
+
+            if !self.is_match(vec![TokenType::COMMA]) {
+                break;
+            }
+        }
+
+        match self.consume(
+            TokenType::SEMICOLON,
+            "Expect ';' after variable declaration.".to_owned(),
+        ) {
+            Ok(_) => Ok(declarations),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Parses an optional `: Type` annotation, returning the type's name
+    fn type_annotation(&mut self) -> Result<Option<Token>, ParseError> {
+        if !self.is_match(vec![TokenType::COLON]) {
+            return Ok(None);
+        }
+
+        match self.consume(
+            TokenType::IDENTIFIER,
+            "Expect type name after ':'.".to_owned(),
+        ) {
+            Ok(type_name) => Ok(Some(type_name)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Parses the `(a, b) = value` part of `var (a, b) = value;`
+    fn unpack_declaration(&mut self, constant: bool, start: Span) -> Result<Stmt, ParseError> {
+        let mut names = vec![];
+
+        loop {
+            match self.consume(TokenType::IDENTIFIER, "Expect variable name.".to_owned()) {
+                Ok(name) => names.push(name),
+                Err(err) => return Err(err),
+            }
+
+            if !self.is_match(vec![TokenType::COMMA]) {
+                break;
             }
-            Err(_) => panic!("Oooooooops"),
+        }
+
+        let paren = self.consume(
+            TokenType::RIGHT_PAREN,
+            "Expect ')' after variable names.".to_owned(),
+        )?;
+
+        match self.consume(
+            TokenType::EQUAL,
+            "Expect '=' after variable names.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
+
+        match self.expression() {
+            Ok(initializer) => Ok(Stmt::Unpack {
+                names,
+                paren,
+                initializer,
+                constant,
+                span: self.span_from(start),
+            }),
+            Err(err) => Err(err),
         }
     }
 
-    fn statement(&mut self) -> Stmt {
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
         if self.is_match(vec![TokenType::FOR]) {
             self.for_statement()
         } else if self.is_match(vec![TokenType::IF]) {
@@ -187,115 +409,211 @@ impl Parser<'_> {
             self.print_statement()
         } else if self.is_match(vec![TokenType::RETURN]) {
             self.return_statement()
+        } else if self.is_match(vec![TokenType::DEFER]) {
+            let keyword = self.previous();
+
+            match self.statement() {
+                Ok(stmt) => Ok(Stmt::Defer {
+                    span: keyword.span.to(stmt.span()),
+                    keyword,
+                    stmt: Box::new(stmt),
+                }),
+                Err(err) => Err(err),
+            }
         } else if self.is_match(vec![TokenType::LEFT_BRACE]) {
-            Stmt::Block {
-                statements: self.block(),
+            let start = self.previous().span;
+
+            match self.block() {
+                Ok(statements) => Ok(Stmt::Block {
+                    statements,
+                    span: self.span_from(start),
+                }),
+                Err(err) => Err(err),
             }
         } else {
             self.expression_statement()
         }
     }
 
-    fn block(&mut self) -> Vec<Stmt> {
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements = vec![];
 
         while !self.check(TokenType::RIGHT_BRACE) && !self.is_at_end() {
-            statements.push(self.declaration());
+            statements.extend(self.declaration());
         }
 
         match self.consume(
             TokenType::RIGHT_BRACE,
             "Expect '}' after block.".to_string(),
         ) {
-            Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
+            Ok(_) => Ok(statements),
+            Err(err) => Err(err),
+        }
+    }
+
+    #[allow(clippy::question_mark)]
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+        let stmt = match self.expression_or_assignment() {
+            Ok(stmt) => stmt,
+            Err(err) => return Err(err),
         };
 
-        statements
+        match self.consume(TokenType::SEMICOLON, "Expect ';' after value.".to_owned()) {
+            Ok(_) => Ok(stmt),
+            Err(err) => Err(err),
+        }
     }
 
-    fn expression_statement(&mut self) -> Stmt {
-        let expr_result = self.expression();
-        let semicolon_result =
-            self.consume(TokenType::SEMICOLON, "Expect ';' after value.".to_owned());
+    // Parses either a plain expression or a parallel assignment like `a, b = b, a`.
+    // The comma only means this at statement level, where it can't be confused
+    // with the separator in argument lists.
+    fn expression_or_assignment(&mut self) -> Result<Stmt, ParseError> {
+        let expr = self.expression()?;
 
-        match semicolon_result {
-            Ok(_) => (),
-            Err(err) => panic!("Panicked parsing expression statement {}", err.message),
+        if !self.check(TokenType::COMMA) {
+            return Ok(Stmt::Expression {
+                span: expr.span(),
+                expr: Box::new(expr),
+            });
         }
 
-        match expr_result {
-            Ok(expr) => Stmt::Expression {
-                expr: Box::new(expr),
-            },
-            Err(err) => panic!("Panicked parsing expression statement {}", err.message),
+        let start = expr.span();
+
+        let mut names = match expr {
+            Expr::Variable { name, span: _ } => vec![name],
+            _ => return Err(self.current_error("Invalid assignment target".to_owned())),
+        };
+
+        while self.is_match(vec![TokenType::COMMA]) {
+            match self.consume(TokenType::IDENTIFIER, "Expect variable name.".to_owned()) {
+                Ok(name) => names.push(name),
+                Err(err) => return Err(err),
+            }
         }
-    }
 
-    fn print_statement(&mut self) -> Stmt {
-        let expr_result = self.expression();
-        let semicolon_result =
-            self.consume(TokenType::SEMICOLON, "Expect ';' after value.".to_owned());
+        let equals = self.consume(
+            TokenType::EQUAL,
+            "Expect '=' after assignment targets.".to_owned(),
+        )?;
 
-        match semicolon_result {
-            Ok(_) => (),
-            Err(err) => panic!("Panicked parsing expression statement {}", err.message),
+        let mut values = vec![];
+
+        loop {
+            match self.expression() {
+                Ok(value) => values.push(value),
+                Err(err) => return Err(err),
+            }
+
+            if !self.is_match(vec![TokenType::COMMA]) {
+                break;
+            }
+        }
+
+        if names.len() != values.len() {
+            return Err(self.error(
+                equals,
+                format!(
+                    "Expected {} values to assign, but got {}.",
+                    names.len(),
+                    values.len()
+                ),
+            ));
         }
 
-        match expr_result {
-            Ok(expr) => Stmt::Print {
+        Ok(Stmt::Assign {
+            names,
+            values,
+            span: self.span_from(start),
+        })
+    }
+
+    #[allow(clippy::question_mark)]
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+        let expr = match self.expression() {
+            Ok(expr) => expr,
+            Err(err) => return Err(err),
+        };
+        let span = self.span_from(start);
+
+        match self.consume(TokenType::SEMICOLON, "Expect ';' after value.".to_owned()) {
+            Ok(_) => Ok(Stmt::Print {
                 expr: Box::new(expr),
-            },
-            Err(_) => panic!("Panicked parsing expression statement"),
+                span,
+            }),
+            Err(err) => Err(err),
         }
     }
 
-    fn return_statement(&mut self) -> Stmt {
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
         let keyword = self.previous();
-        let mut value = Expr::Literal { value: Value::Nil };
+        // A bare `return` returns an implicit nil with nothing in the source to point at
+        let mut value = Expr::Literal {
+            value: Value::Nil,
+            span: Span::new(keyword.span.end, keyword.span.end),
+        };
 
         if !self.check(TokenType::SEMICOLON) {
-            value = self.expression().unwrap();
+            match self.expression() {
+                Ok(expr) => value = expr,
+                Err(err) => return Err(err),
+            }
         }
+        let span = self.span_from(keyword.span);
 
         match self.consume(
             TokenType::SEMICOLON,
-            "Expect ';' after 'return'.".to_owned(),
+            "Expect ';' after return value.".to_owned(),
         ) {
-            Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
-        }
-
-        Stmt::Return {
-            keyword,
-            value: Box::new(value),
+            Ok(_) => Ok(Stmt::Return {
+                keyword,
+                value: Box::new(value),
+                span,
+            }),
+            Err(err) => Err(err),
         }
     }
 
-    fn for_statement(&mut self) -> Stmt {
+    #[allow(clippy::question_mark)]
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+
         // Here, we de-sugar a for loop into a while loop
-        match self.consume(
-            TokenType::LEFT_PAREN,
-            "Expect '(' after 'while'.".to_owned(),
-        ) {
+        match self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'for'.".to_owned()) {
             Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
+            Err(err) => return Err(err),
         }
 
-        let initializer;
+        if self.check(TokenType::VAR)
+            && self.check_ahead(1, TokenType::IDENTIFIER)
+            && self.check_ahead(2, TokenType::IN)
+        {
+            return self.for_in_statement(start);
+        }
 
-        if self.is_match(vec![TokenType::SEMICOLON]) {
-            initializer = None;
+        let initializer_result = if self.is_match(vec![TokenType::SEMICOLON]) {
+            Ok(vec![])
         } else if self.is_match(vec![TokenType::VAR]) {
-            initializer = Some(self.var_declaration());
+            self.var_declaration(false)
         } else {
-            initializer = Some(self.expression_statement());
-        }
+            self.expression_statement().map(|stmt| vec![stmt])
+        };
+
+        let initializer = match initializer_result {
+            Ok(initializer) => initializer,
+            Err(err) => return Err(err),
+        };
 
-        let mut condition = None;
+        let mut condition = Expr::Literal {
+            value: Value::Boolean { value: true },
+            span: Span::new(self.peek().span.start, self.peek().span.start),
+        };
 
         if !self.check(TokenType::SEMICOLON) {
-            condition.replace(self.expression());
+            match self.expression() {
+                Ok(condition_expr) => condition = condition_expr,
+                Err(err) => return Err(err),
+            }
         }
 
         match self.consume(
@@ -303,13 +621,16 @@ impl Parser<'_> {
             "Expect ';' after loop condition.".to_owned(),
         ) {
             Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
+            Err(err) => return Err(err),
         }
 
         let mut increment = None;
 
         if !self.check(TokenType::RIGHT_PAREN) {
-            increment.replace(self.expression());
+            match self.expression_or_assignment() {
+                Ok(increment_stmt) => increment = Some(increment_stmt),
+                Err(err) => return Err(err),
+            }
         }
 
         match self.consume(
@@ -317,110 +638,140 @@ impl Parser<'_> {
             "Expect ')' after for clauses.".to_owned(),
         ) {
             Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
+            Err(err) => return Err(err),
         }
 
-        let mut body = self.statement();
+        let mut body = match self.statement() {
+            Ok(body) => body,
+            Err(err) => return Err(err),
+        };
+
+        let span = self.span_from(start);
 
-        if let Some(Ok(increment_expr)) = increment {
-            // TODO Handle the err
+        if let Some(increment_stmt) = increment {
             body = Stmt::Block {
-                statements: vec![
-                    body,
-                    Stmt::Expression {
-                        expr: Box::new(increment_expr),
-                    },
-                ],
+                statements: vec![body, increment_stmt],
+                span,
             }
         }
 
-        if condition.is_none() {
-            condition.replace(Ok(Expr::Literal {
-                value: Value::Boolean { value: true },
-            }));
-        }
+        body = Stmt::While {
+            condition,
+            body: Box::new(body),
+            span,
+        };
 
-        match condition {
-            Some(Ok(condition_expr)) => {
-                body = Stmt::While {
-                    condition: condition_expr,
-                    body: Box::new(body),
-                };
-            }
-            Some(Err(err)) => panic!("{:?}", err),
-            _ => panic!("This shouldn't happen"),
+        if !initializer.is_empty() {
+            let mut statements = initializer;
+            statements.push(body);
+            body = Stmt::Block { statements, span };
         }
 
-        match initializer {
-            Some(init_stmt) => {
-                body = Stmt::Block {
-                    statements: vec![init_stmt, body],
-                }
-            }
-            None => (),
+        Ok(body)
+    }
+
+    fn for_in_statement(&mut self, start: Span) -> Result<Stmt, ParseError> {
+        self.advance();
+        let name = self.advance();
+        self.advance();
+
+        let iterable = self.expression()?;
+
+        match self.consume(
+            TokenType::RIGHT_PAREN,
+            "Expect ')' after for clauses.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
         }
 
-        body
+        match self.statement() {
+            Ok(body) => Ok(Stmt::ForIn {
+                name,
+                iterable,
+                body: Box::new(body),
+                span: self.span_from(start),
+            }),
+            Err(err) => Err(err),
+        }
     }
 
-    fn while_statement(&mut self) -> Stmt {
+    #[allow(clippy::question_mark)]
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+
         match self.consume(
             TokenType::LEFT_PAREN,
             "Expect '(' after 'while'.".to_owned(),
         ) {
             Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
+            Err(err) => return Err(err),
         }
 
-        match self.expression() {
-            Ok(condition) => {
-                match self.consume(
-                    TokenType::RIGHT_PAREN,
-                    "Expect ')' after condition.".to_owned(),
-                ) {
-                    Ok(_) => (),
-                    Err(err) => panic!("{:?}", err),
-                }
+        let condition = match self.expression() {
+            Ok(condition) => condition,
+            Err(err) => return Err(err),
+        };
 
-                let body = Box::new(self.statement());
+        match self.consume(
+            TokenType::RIGHT_PAREN,
+            "Expect ')' after condition.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
 
-                Stmt::While { condition, body }
-            }
-            Err(err) => panic!("{:?}", err),
+        match self.statement() {
+            Ok(body) => Ok(Stmt::While {
+                condition,
+                body: Box::new(body),
+                span: self.span_from(start),
+            }),
+            Err(err) => Err(err),
         }
     }
 
-    fn if_statement(&mut self) -> Stmt {
+    #[allow(clippy::question_mark)]
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+
         match self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'if'.".to_owned()) {
             Ok(_) => (),
-            Err(err) => panic!("{:?}", err),
+            Err(err) => return Err(err),
         }
 
-        match self.expression() {
-            Ok(condition) => {
-                match self.consume(
-                    TokenType::RIGHT_PAREN,
-                    "Expect ')' after if condition.".to_owned(),
-                ) {
-                    Ok(_) => (),
-                    Err(err) => panic!("{:?}", err),
-                }
+        let condition = match self.expression() {
+            Ok(condition) => condition,
+            Err(err) => return Err(err),
+        };
 
-                let then_branch = Box::new(self.statement());
-                let mut else_branch = None;
+        match self.consume(
+            TokenType::RIGHT_PAREN,
+            "Expect ')' after if condition.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
 
-                if self.is_match(vec![TokenType::ELSE]) {
-                    else_branch.replace(Box::new(self.statement()));
-                }
+        let then_branch = match self.statement() {
+            Ok(then_branch) => Box::new(then_branch),
+            Err(err) => return Err(err),
+        };
+        let mut else_branch = None;
 
-                Stmt::If {
-                    condition,
-                    then_branch,
-                    else_branch,
-                }
+        if self.is_match(vec![TokenType::ELSE]) {
+            match self.statement() {
+                Ok(else_stmt) => else_branch = Some(Box::new(else_stmt)),
+                Err(err) => return Err(err),
             }
-            Err(err) => panic!("{:?}", err),
         }
+
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: self.span_from(start),
+        })
     }
 
     fn expression(&mut self) -> Result<Expr, ParseError> {
@@ -428,7 +779,7 @@ impl Parser<'_> {
     }
 
     fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or();
+        let expr = self.pipeline();
 
         if self.is_match(vec![TokenType::EQUAL]) {
             let _equals = self.previous();
@@ -437,9 +788,10 @@ impl Parser<'_> {
             match expr {
                 Ok(expression) => match value {
                     Ok(value_expr) => match expression {
-                        Expr::Variable { name } => {
+                        Expr::Variable { name, span: _ } => {
                             return Ok(Expr::Assign {
-                                name: name,
+                                span: name.span.to(value_expr.span()),
+                                name,
                                 value: Box::new(value_expr),
                             })
                         }
@@ -454,62 +806,215 @@ impl Parser<'_> {
         expr
     }
 
-    fn or(&mut self) -> Result<Expr, ParseError> {
-        match self.and() {
+    // `x |> f(y)` is sugar for `f(x, y)`, and `x |> f` for `f(x)`. The pipe token
+    // stands in for the call's closing paren so that call errors point at it.
+    fn pipeline(&mut self) -> Result<Expr, ParseError> {
+        match self.coalesce() {
+            Ok(mut expr) => {
+                while self.is_match(vec![TokenType::PIPE_GREATER]) {
+                    let pipe = self.previous();
+
+                    match self.coalesce() {
+                        Ok(Expr::Call {
+                            callee,
+                            paren: _,
+                            mut arguments,
+                            optional,
+                            span: _,
+                        }) => {
+                            let span = self.span_from(expr.span());
+                            arguments.insert(0, expr);
+                            expr = Expr::Call {
+                                callee,
+                                paren: pipe,
+                                arguments,
+                                optional,
+                                span,
+                            }
+                        }
+                        Ok(callee) => {
+                            expr = Expr::Call {
+                                span: self.span_from(expr.span()),
+                                callee: Box::new(callee),
+                                paren: pipe,
+                                arguments: vec![expr],
+                                optional: false,
+                            }
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok(expr)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn coalesce(&mut self) -> Result<Expr, ParseError> {
+        match self.or() {
+            Ok(mut expr) => {
+                while self.is_match(vec![TokenType::QUESTION_QUESTION]) {
+                    let operator = self.previous();
+                    match self.or() {
+                        Ok(right) => {
+                            expr = Expr::Logical {
+                                span: expr.span().to(right.span()),
+                                left: Box::new(expr),
+                                operator,
+                                right: Box::new(right),
+                            }
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok(expr)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    #[allow(clippy::needless_return)]
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        match self.and() {
+            Ok(mut expr) => {
+                while self.is_match(vec![TokenType::OR]) {
+                    let operator = self.previous();
+                    match self.and() {
+                        Ok(right) => {
+                            expr = Expr::Logical {
+                                span: expr.span().to(right.span()),
+                                left: Box::new(expr),
+                                operator,
+                                right: Box::new(right),
+                            }
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok(expr)
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    #[allow(clippy::needless_return)]
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        match self.equality() {
+            Ok(mut expr) => {
+                while self.is_match(vec![TokenType::AND]) {
+                    let operator = self.previous();
+                    match self.equality() {
+                        Ok(right) => {
+                            expr = Expr::Logical {
+                                span: expr.span().to(right.span()),
+                                left: Box::new(expr),
+                                operator,
+                                right: Box::new(right),
+                            }
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok(expr)
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    #[allow(clippy::needless_return)]
+    fn equality(&mut self) -> Result<Expr, ParseError> {
+        match self.bit_or() {
+            Ok(mut expr) => {
+                while self.is_match(vec![TokenType::BANG_EQUAL, TokenType::EQUAL_EQUAL]) {
+                    let operator = self.previous();
+                    let right_result = self.bit_or();
+
+                    match right_result {
+                        Ok(right) => {
+                            expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
+                                left: Box::new(expr),
+                                operator,
+                                right: Box::new(right),
+                            };
+                        }
+                        Err(parse_error) => return Err(parse_error),
+                    }
+                }
+                Ok(expr)
+            }
+
+            Err(parse_error) => return Err(parse_error),
+        }
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, ParseError> {
+        match self.bit_xor() {
             Ok(mut expr) => {
-                while self.is_match(vec![TokenType::OR]) {
+                while self.is_match(vec![TokenType::PIPE]) {
                     let operator = self.previous();
-                    match self.and() {
+                    let right_result = self.bit_xor();
+
+                    match right_result {
                         Ok(right) => {
-                            expr = Expr::Logical {
+                            expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: Box::new(expr),
                                 operator,
                                 right: Box::new(right),
-                            }
+                            };
                         }
-                        Err(err) => return Err(err),
+                        Err(parse_error) => return Err(parse_error),
                     }
                 }
 
                 Ok(expr)
             }
-            Err(err) => return Err(err),
+            Err(parse_error) => Err(parse_error),
         }
     }
 
-    fn and(&mut self) -> Result<Expr, ParseError> {
-        match self.equality() {
+    fn bit_xor(&mut self) -> Result<Expr, ParseError> {
+        match self.bit_and() {
             Ok(mut expr) => {
-                while self.is_match(vec![TokenType::AND]) {
+                while self.is_match(vec![TokenType::CARET]) {
                     let operator = self.previous();
-                    match self.equality() {
+                    let right_result = self.bit_and();
+
+                    match right_result {
                         Ok(right) => {
-                            expr = Expr::Logical {
+                            expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: Box::new(expr),
                                 operator,
                                 right: Box::new(right),
-                            }
+                            };
                         }
-                        Err(err) => return Err(err),
+                        Err(parse_error) => return Err(parse_error),
                     }
                 }
 
                 Ok(expr)
             }
-            Err(err) => return Err(err),
+            Err(parse_error) => Err(parse_error),
         }
     }
 
-    fn equality(&mut self) -> Result<Expr, ParseError> {
+    fn bit_and(&mut self) -> Result<Expr, ParseError> {
         match self.comparison() {
             Ok(mut expr) => {
-                while self.is_match(vec![TokenType::BANG_EQUAL, TokenType::EQUAL_EQUAL]) {
+                while self.is_match(vec![TokenType::AMPERSAND]) {
                     let operator = self.previous();
                     let right_result = self.comparison();
 
                     match right_result {
                         Ok(right) => {
                             expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: Box::new(expr),
                                 operator,
                                 right: Box::new(right),
@@ -518,28 +1023,31 @@ impl Parser<'_> {
                         Err(parse_error) => return Err(parse_error),
                     }
                 }
+
                 Ok(expr)
             }
-
-            Err(parse_error) => return Err(parse_error),
+            Err(parse_error) => Err(parse_error),
         }
     }
 
+    #[allow(clippy::needless_return)]
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        match self.term() {
+        match self.range() {
             Ok(mut expr) => {
                 while self.is_match(vec![
                     TokenType::GREATER,
                     TokenType::GREATER_EQUAL,
                     TokenType::LESS,
                     TokenType::LESS_EQUAL,
+                    TokenType::IN,
                 ]) {
                     let operator = self.previous();
-                    let right_result = self.term();
+                    let right_result = self.range();
 
                     match right_result {
                         Ok(right) => {
                             expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: Box::new(expr),
                                 operator,
                                 right: Box::new(right),
@@ -555,6 +1063,58 @@ impl Parser<'_> {
         }
     }
 
+    // Ranges don't chain, so unlike the other binary levels there's no loop here
+    fn range(&mut self) -> Result<Expr, ParseError> {
+        match self.shift() {
+            Ok(expr) => {
+                if self.is_match(vec![TokenType::DOT_DOT, TokenType::DOT_DOT_EQUAL]) {
+                    let operator = self.previous();
+
+                    match self.shift() {
+                        Ok(right) => {
+                            return Ok(Expr::Binary {
+                                span: expr.span().to(right.span()),
+                                left: Box::new(expr),
+                                operator,
+                                right: Box::new(right),
+                            })
+                        }
+                        Err(parse_error) => return Err(parse_error),
+                    }
+                }
+
+                Ok(expr)
+            }
+            Err(parse_error) => Err(parse_error),
+        }
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        match self.term() {
+            Ok(mut expr) => {
+                while self.is_match(vec![TokenType::LESS_LESS, TokenType::GREATER_GREATER]) {
+                    let operator = self.previous();
+                    let right_result = self.term();
+
+                    match right_result {
+                        Ok(right) => {
+                            expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
+                                left: Box::new(expr),
+                                operator,
+                                right: Box::new(right),
+                            };
+                        }
+                        Err(parse_error) => return Err(parse_error),
+                    }
+                }
+
+                Ok(expr)
+            }
+            Err(parse_error) => Err(parse_error),
+        }
+    }
+
     fn term(&mut self) -> Result<Expr, ParseError> {
         match self.factor() {
             Ok(mut expr) => {
@@ -565,12 +1125,13 @@ impl Parser<'_> {
                     match right_result {
                         Ok(right) => {
                             expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: Box::new(expr),
                                 operator,
                                 right: Box::new(right),
                             };
                         }
-                        Err(_) => {}
+                        Err(parse_error) => return Err(parse_error),
                     }
                 }
 
@@ -580,6 +1141,7 @@ impl Parser<'_> {
         }
     }
 
+    #[allow(clippy::needless_return)]
     fn factor(&mut self) -> Result<Expr, ParseError> {
         match self.unary() {
             Ok(mut expr) => {
@@ -589,6 +1151,7 @@ impl Parser<'_> {
                     match right_result {
                         Ok(right) => {
                             expr = Expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: Box::new(expr),
                                 operator,
                                 right: Box::new(right),
@@ -605,13 +1168,14 @@ impl Parser<'_> {
     }
 
     fn unary(&mut self) -> Result<Expr, ParseError> {
-        if self.is_match(vec![TokenType::BANG, TokenType::MINUS]) {
+        if self.is_match(vec![TokenType::BANG, TokenType::MINUS, TokenType::TILDE]) {
             let operator = self.previous();
             let right_result = self.unary();
 
             match right_result {
                 Ok(right) => {
                     return Ok(Expr::Unary {
+                        span: operator.span.to(right.span()),
                         operator,
                         right: Box::new(right),
                     });
@@ -623,15 +1187,47 @@ impl Parser<'_> {
         self.call()
     }
 
+    #[allow(clippy::needless_return)]
     fn call(&mut self) -> Result<Expr, ParseError> {
         match self.primary() {
             Ok(mut expr) => {
                 loop {
                     if self.is_match(vec![TokenType::LEFT_PAREN]) {
-                        match self.finish_call(expr) {
+                        match self.finish_call(expr, false) {
+                            Ok(finish_call_expr) => expr = finish_call_expr,
+                            Err(err) => return Err(err),
+                        }
+                    } else if self.is_match(vec![TokenType::QUESTION_DOT]) {
+                        match self
+                            .consume(TokenType::LEFT_PAREN, "Expect '(' after '?.'.".to_owned())
+                        {
+                            Ok(_) => (),
+                            Err(err) => return Err(err),
+                        }
+
+                        match self.finish_call(expr, true) {
                             Ok(finish_call_expr) => expr = finish_call_expr,
                             Err(err) => return Err(err),
                         }
+                    } else if self.is_match(vec![TokenType::LEFT_BRACKET]) {
+                        match self.finish_index(expr) {
+                            Ok(index_expr) => expr = index_expr,
+                            Err(err) => return Err(err),
+                        }
+                    } else if self.is_match(vec![TokenType::DOT]) {
+                        match self.consume(
+                            TokenType::IDENTIFIER,
+                            "Expect property name after '.'.".to_owned(),
+                        ) {
+                            Ok(name) => {
+                                expr = Expr::Get {
+                                    span: expr.span().to(name.span),
+                                    object: Box::new(expr),
+                                    name,
+                                }
+                            }
+                            Err(err) => return Err(err),
+                        }
                     } else {
                         break;
                     }
@@ -643,7 +1239,7 @@ impl Parser<'_> {
         }
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+    fn finish_call(&mut self, callee: Expr, optional: bool) -> Result<Expr, ParseError> {
         let mut arguments = vec![];
 
         if !self.check(TokenType::RIGHT_PAREN) {
@@ -671,41 +1267,120 @@ impl Parser<'_> {
             "Expect ')' after arguments.".to_string(),
         ) {
             Ok(paren) => Ok(Expr::Call {
+                span: callee.span().to(paren.span),
                 callee: Box::new(callee),
                 paren,
                 arguments,
+                optional,
             }),
             Err(err) => Err(err),
         }
     }
 
+    // Parses what follows `[`, which is either an index `s[i]` or a slice `s[start:end]`
+    fn finish_index(&mut self, object: Expr) -> Result<Expr, ParseError> {
+        let bracket = self.previous();
+        let mut start = None;
+
+        if !self.check(TokenType::COLON) {
+            match self.expression() {
+                Ok(expr) => start = Some(Box::new(expr)),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let slice = self.is_match(vec![TokenType::COLON]);
+        let mut end = None;
+
+        if slice && !self.check(TokenType::RIGHT_BRACKET) {
+            match self.expression() {
+                Ok(expr) => end = Some(Box::new(expr)),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if !slice && start.is_none() {
+            return Err(self.current_error("Expect index expression.".to_owned()));
+        }
+
+        match self.consume(
+            TokenType::RIGHT_BRACKET,
+            "Expect ']' after index.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
+
+        let span = self.span_from(object.span());
+
+        match start {
+            Some(index) if !slice => Ok(Expr::Index {
+                object: Box::new(object),
+                bracket,
+                index,
+                span,
+            }),
+            _ => Ok(Expr::Slice {
+                object: Box::new(object),
+                bracket,
+                start,
+                end,
+                span,
+            }),
+        }
+    }
+
     fn primary(&mut self) -> Result<Expr, ParseError> {
         if self.is_match(vec![TokenType::FALSE]) {
             return Ok(Expr::Literal {
                 value: Value::Boolean { value: false },
+                span: self.previous().span,
             });
         }
         if self.is_match(vec![TokenType::TRUE]) {
             return Ok(Expr::Literal {
                 value: Value::Boolean { value: true },
+                span: self.previous().span,
             });
         }
         if self.is_match(vec![TokenType::NIL]) {
-            return Ok(Expr::Literal { value: Value::Nil });
+            return Ok(Expr::Literal {
+                value: Value::Nil,
+                span: self.previous().span,
+            });
         }
         if self.is_match(vec![TokenType::NUMBER, TokenType::STRING]) {
             return Ok(Expr::Literal {
                 value: self.previous().literal.unwrap(),
+                span: self.previous().span,
             });
         }
         if self.is_match(vec![TokenType::IDENTIFIER]) {
             return Ok(Expr::Variable {
                 name: self.previous(),
+                span: self.previous().span,
             });
         }
         if self.is_match(vec![TokenType::LEFT_PAREN]) {
+            let start = self.previous().span;
+
+            if self.is_match(vec![TokenType::RIGHT_PAREN]) {
+                return Ok(Expr::Tuple {
+                    elements: vec![],
+                    span: self.span_from(start),
+                });
+            }
+
             let expr_result = self.expression();
 
+            // A comma turns the parentheses into a tuple, `(a,)` being the 1-tuple
+            if self.check(TokenType::COMMA) {
+                return match expr_result {
+                    Ok(expr) => self.finish_tuple(expr, start),
+                    Err(parse_error) => Err(parse_error),
+                };
+            }
+
             let result = self.consume(
                 TokenType::RIGHT_PAREN,
                 "Expect ')' after expression.".to_string(),
@@ -720,17 +1395,213 @@ impl Parser<'_> {
                 Ok(expr) => {
                     return Ok(Expr::Grouping {
                         expression: Box::new(expr),
+                        span: self.span_from(start),
                     });
                 }
                 Err(parse_error) => return Err(parse_error),
             }
         }
-        Err(self.current_error(format!("this shouldn't happen {:?}", self.peek())))
+        if self.is_match(vec![TokenType::IF]) {
+            return self.if_expression();
+        }
+        if self.is_match(vec![TokenType::LEFT_BRACE]) {
+            return self.block_expression();
+        }
+        Err(self.current_error("Expect expression.".to_owned()))
+    }
+
+    fn if_expression(&mut self) -> Result<Expr, ParseError> {
+        let start = self.previous().span;
+
+        match self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'if'.".to_owned()) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
+
+        let condition = self.expression()?;
+
+        match self.consume(
+            TokenType::RIGHT_PAREN,
+            "Expect ')' after if condition.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
+
+        match self.consume(
+            TokenType::LEFT_BRACE,
+            "Expect '{' before if expression body.".to_owned(),
+        ) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        }
+
+        let then_branch = self.block_expression()?;
+
+        let mut else_branch = None;
+
+        if self.is_match(vec![TokenType::ELSE]) {
+            let else_expr = if self.is_match(vec![TokenType::IF]) {
+                self.if_expression()
+            } else {
+                match self.consume(TokenType::LEFT_BRACE, "Expect '{' after 'else'.".to_owned()) {
+                    Ok(_) => self.block_expression(),
+                    Err(err) => return Err(err),
+                }
+            };
+
+            match else_expr {
+                Ok(else_expr) => else_branch = Some(Box::new(else_expr)),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Expr::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+            span: self.span_from(start),
+        })
+    }
+
+    // Parses the rest of a block after its '{'. The last expression, if it has no
+    // semicolon, becomes the value of the block. Nested blocks and if expressions
+    // don't need a semicolon to be used as statements.
+    fn block_expression(&mut self) -> Result<Expr, ParseError> {
+        let start = self.previous().span;
+        let mut statements = vec![];
+        let mut value = None;
+
+        while !self.check(TokenType::RIGHT_BRACE) && !self.is_at_end() {
+            if self.check(TokenType::LEFT_BRACE)
+                || (self.check(TokenType::IF) && self.if_has_block_body())
+            {
+                // An if with block bodies may still be an if statement, like one
+                // with an unbraced else. If it doesn't parse as an expression we
+                // go back and parse it as a statement instead.
+                let checkpoint = (self.current, self.errors.len());
+                let expr = match self.expression() {
+                    Ok(expr) => expr,
+                    Err(_) if self.tokens[checkpoint.0 as usize].ttype == TokenType::IF => {
+                        self.current = checkpoint.0;
+                        self.errors.truncate(checkpoint.1);
+                        statements.extend(self.declaration());
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+
+                if !self.is_match(vec![TokenType::SEMICOLON]) && self.check(TokenType::RIGHT_BRACE)
+                {
+                    value = Some(Box::new(expr));
+                    break;
+                }
+
+                statements.push(Stmt::Expression {
+                    span: expr.span(),
+                    expr: Box::new(expr),
+                });
+            } else if self.check_any(vec![
+                TokenType::VAR,
+                TokenType::CONST,
+                TokenType::FUN,
+                TokenType::ENUM,
+                TokenType::AT,
+                TokenType::FOR,
+                TokenType::IF,
+                TokenType::WHILE,
+                TokenType::PRINT,
+                TokenType::RETURN,
+                TokenType::DEFER,
+            ]) {
+                statements.extend(self.declaration());
+            } else {
+                let stmt = self.expression_or_assignment()?;
+
+                if self.is_match(vec![TokenType::SEMICOLON]) {
+                    statements.push(stmt);
+                } else {
+                    match (stmt, self.check(TokenType::RIGHT_BRACE)) {
+                        (Stmt::Expression { expr, span: _ }, true) => {
+                            value = Some(expr);
+                            break;
+                        }
+                        _ => return Err(self.current_error("Expect ';' after value.".to_owned())),
+                    }
+                }
+            }
+        }
+
+        match self.consume(
+            TokenType::RIGHT_BRACE,
+            "Expect '}' after block.".to_string(),
+        ) {
+            Ok(_) => Ok(Expr::Block {
+                statements,
+                value,
+                span: self.span_from(start),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    // With the current token on an `if`, looks past the condition to tell an if
+    // expression, whose body is always a block, from an if statement
+    fn if_has_block_body(&self) -> bool {
+        if !self.check_ahead(1, TokenType::LEFT_PAREN) {
+            return false;
+        }
+
+        let mut depth = 0;
+        let mut offset = 1;
+
+        while let Some(token) = self.tokens.get(self.current as usize + offset) {
+            match token.ttype {
+                TokenType::LEFT_PAREN => depth += 1,
+                TokenType::RIGHT_PAREN => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.check_ahead(offset + 1, TokenType::LEFT_BRACE);
+                    }
+                }
+                TokenType::EOF => return false,
+                _ => (),
+            }
+            offset += 1;
+        }
+
+        false
+    }
+
+    fn finish_tuple(&mut self, first: Expr, start: Span) -> Result<Expr, ParseError> {
+        let mut elements = vec![first];
+
+        while self.is_match(vec![TokenType::COMMA]) {
+            if self.check(TokenType::RIGHT_PAREN) {
+                break;
+            }
+
+            match self.expression() {
+                Ok(element) => elements.push(element),
+                Err(err) => return Err(err),
+            }
+        }
+
+        match self.consume(
+            TokenType::RIGHT_PAREN,
+            "Expect ')' after tuple elements.".to_string(),
+        ) {
+            Ok(_) => Ok(Expr::Tuple {
+                elements,
+                span: self.span_from(start),
+            }),
+            Err(err) => Err(err),
+        }
     }
 
     fn is_match(&mut self, ttypes: Vec<TokenType>) -> bool {
         for ttype in ttypes.iter() {
-            if self.check(ttype.clone()) {
+            if self.check(*ttype) {
                 self.advance();
                 return true;
             }
@@ -746,6 +1617,17 @@ impl Parser<'_> {
         self.peek().ttype == ttype
     }
 
+    fn check_any(&self, ttypes: Vec<TokenType>) -> bool {
+        ttypes.into_iter().any(|ttype| self.check(ttype))
+    }
+
+    fn check_ahead(&self, offset: usize, ttype: TokenType) -> bool {
+        match self.tokens.get(self.current as usize + offset) {
+            Some(token) => token.ttype == ttype,
+            None => false,
+        }
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().ttype == TokenType::EOF
     }
@@ -758,6 +1640,12 @@ impl Parser<'_> {
         self.tokens[(self.current - 1) as usize].clone()
     }
 
+    // From `start` to the end of the last token consumed
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
+    }
+
+    #[allow(clippy::needless_return)]
     fn consume(&mut self, ttype: TokenType, message: String) -> Result<Token, ParseError> {
         if self.check(ttype) {
             return Ok(self.advance());
@@ -779,41 +1667,196 @@ impl Parser<'_> {
         self.previous()
     }
 
-    // fn synchronize(&mut self) {
-    //     self.advance();
-
-    //     while !self.is_at_end() {
-    //         if self.previous().ttype == TokenType::SEMICOLON {
-    //             return;
-    //         }
-
-    //         match self.peek().ttype {
-    //             TokenType::CLASS => return,
-    //             TokenType::FUN => return,
-    //             TokenType::VAR => return,
-    //             TokenType::FOR => return,
-    //             TokenType::IF => return,
-    //             TokenType::WHILE => return,
-    //             TokenType::PRINT => return,
-    //             TokenType::RETURN => return,
-    //             _ => self.advance(),
-    //         };
-    //     }
-    // }
+    // Discards tokens until we're probably at the start of the next statement,
+    // so that one mistake doesn't cascade into a string of bogus errors
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().ttype == TokenType::SEMICOLON {
+                return;
+            }
+
+            match self.peek().ttype {
+                TokenType::CLASS => return,
+                TokenType::FUN => return,
+                TokenType::VAR => return,
+                TokenType::CONST => return,
+                TokenType::ENUM => return,
+                TokenType::AT => return,
+                TokenType::FOR => return,
+                TokenType::IF => return,
+                TokenType::WHILE => return,
+                TokenType::PRINT => return,
+                TokenType::RETURN => return,
+                TokenType::DEFER => return,
+                _ => self.advance(),
+            };
+        }
+    }
 
     fn error(&self, token: Token, message: String) -> ParseError {
         if token.ttype == TokenType::EOF {
             ParseError {
                 message,
                 line: token.line,
-                error_where: " at end ".to_owned(),
+                error_where: " at end".to_owned(),
+                span: token.span,
             }
         } else {
             ParseError {
                 message,
                 line: token.line,
                 error_where: (" at '".to_string() + &token.lexeme.to_string() + "'"),
+                span: token.span,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        expression::{Expr, Stmt},
+        token::{Scanner, Span, TokenType},
+    };
+
+    use super::Parser;
+
+    #[test]
+    fn it_rejects_a_parallel_assignment_with_mismatched_arity() {
+        let (tokens, _) = Scanner::new("a, b = 1, 2, 3").scan_tokens();
+        let mut parser = Parser::new(&tokens);
+
+        let error = parser.expression_or_assignment().unwrap_err();
+
+        assert_eq!("Expected 2 values to assign, but got 3.", error.message);
+        assert_eq!(" at '='", error.error_where);
+    }
+
+    #[test]
+    fn it_desugars_a_pipeline_into_a_call_with_the_piped_value_first() {
+        let (tokens, _) = Scanner::new("x |> f(y)").scan_tokens();
+        let mut parser = Parser::new(&tokens);
+
+        match parser.expression().unwrap() {
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                optional: _,
+                span: _,
+            } => {
+                assert!(
+                    matches!(*callee, Expr::Variable { ref name, span: _ } if name.lexeme == "f")
+                );
+                assert_eq!(TokenType::PIPE_GREATER, paren.ttype);
+                assert!(matches!(&arguments[..], [
+                    Expr::Variable { name: first, span: _ },
+                    Expr::Variable { name: second, span: _ },
+                ] if first.lexeme == "x" && second.lexeme == "y"));
+            }
+            expr => panic!("Expected a call, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn it_reports_every_syntax_error_and_keeps_the_valid_statements() {
+        let (tokens, _) =
+            Scanner::new("var a = ;\nprint 1;\nfun (x) {}\nvar b = 2\nprint 3;").scan_tokens();
+
+        let (statements, errors) = Parser::new(&tokens).parse();
+
+        assert_eq!(3, errors.len());
+        assert_eq!(1, errors[0].line);
+        assert_eq!("Expect expression.", errors[0].message);
+        assert_eq!(3, errors[1].line);
+        assert_eq!("Expect function name.", errors[1].message);
+        assert_eq!(5, errors[2].line);
+        assert_eq!("Expect ';' after variable declaration.", errors[2].message);
+        assert_eq!(" at 'print'", errors[2].error_where);
+        // Only `print 1;` survives, since recovering from the missing ';' skips the
+        // statement the error was reported at
+        assert_eq!(1, statements.len());
+    }
+
+    #[test]
+    fn it_recovers_from_an_error_inside_a_block() {
+        let (tokens, _) = Scanner::new("{ print ; print 2; }\nprint 3;").scan_tokens();
+
+        let (statements, errors) = Parser::new(&tokens).parse();
+
+        assert_eq!(1, errors.len());
+        assert_eq!(2, statements.len());
+    }
+
+    #[test]
+    fn it_parses_an_if_statement_inside_a_block_expression() {
+        let (tokens, _) =
+            Scanner::new("var r = { if (c) { print 1; } else print 2; 3 };").scan_tokens();
+
+        let (statements, errors) = Parser::new(&tokens).parse();
+
+        assert_eq!(0, errors.len());
+        match &statements[0] {
+            Stmt::Var {
+                initializer:
+                    Some(Expr::Block {
+                        statements, value, ..
+                    }),
+                ..
+            } => {
+                assert!(matches!(statements[..], [Stmt::If { .. }]));
+                assert!(value.is_some());
+            }
+            stmt => panic!("Expected a variable declaration, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn it_reports_an_error_at_the_end_of_the_source() {
+        let (tokens, _) = Scanner::new("print (1").scan_tokens();
+
+        let (_, errors) = Parser::new(&tokens).parse();
+
+        assert_eq!(1, errors.len());
+        assert_eq!(" at end", errors[0].error_where);
+        assert_eq!("Expect ')' after expression.", errors[0].message);
+    }
+
+    #[test]
+    fn it_gives_expressions_spans_covering_their_source() {
+        let source = "(a + b) * f(1)[0]";
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let mut parser = Parser::new(&tokens);
+
+        match parser.expression().unwrap() {
+            Expr::Binary {
+                left, right, span, ..
+            } => {
+                assert_eq!(Span::new(0, 17), span);
+                assert_eq!("(a + b)", &source[left.span().start..left.span().end]);
+                assert_eq!("f(1)[0]", &source[right.span().start..right.span().end]);
             }
+            expr => panic!("Expected a binary expression, got {:?}", expr),
         }
     }
+
+    #[test]
+    fn it_gives_statements_spans_that_stop_before_their_semicolon() {
+        let source = "var x = 1;\nwhile (x < 3) {\n  x = x + 1;\n}\nprint x;";
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+
+        let texts: Vec<&str> = statements
+            .iter()
+            .map(|stmt| &source[stmt.span().start..stmt.span().end])
+            .collect();
+
+        assert_eq!(
+            vec!["var x = 1", "while (x < 3) {\n  x = x + 1;\n}", "print x"],
+            texts
+        );
+        assert!(matches!(statements[1], Stmt::While { .. }));
+    }
 }
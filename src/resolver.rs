@@ -0,0 +1,938 @@
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::{
+        position, Diagnostic, CONSTANT_CONDITION, RESOLVE_ERROR, SHADOWING, UNREACHABLE_CODE,
+        UNUSED_VARIABLE,
+    },
+    expression::{Expr, ExprVisitor, Stmt, StmtVisitor},
+    token::{Comment, Span, Token, Value},
+};
+
+// Walks the tree before it is interpreted, tracking lexical scopes so that
+// mistakes which are visible in the source can be reported up front, and
+// suspicious code can be warned about.
+pub struct Resolver {
+    scopes: Vec<HashMap<String, Binding>>,
+    // How many function bodies we're inside, so we can reject a top-level return
+    function_depth: usize,
+    errors: Vec<ResolverError>,
+    warnings: Vec<Warning>,
+}
+
+// What we know about a name declared in some scope
+struct Binding {
+    name: Token,
+    kind: BindingKind,
+    constant: bool,
+    // Whether the name has been read. Assigning to it doesn't count.
+    used: bool,
+}
+
+#[derive(PartialEq)]
+enum BindingKind {
+    Variable,
+    Parameter,
+    // Functions and enums, which aren't reported when unused
+    Declaration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolverError {
+    pub token: Token,
+    pub message: String,
+    // The declaration of the constant being assigned to
+    pub declared_at: Option<Span>,
+    // Whether that constant is a `const` parameter rather than a variable
+    pub parameter: bool,
+}
+
+impl ResolverError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(RESOLVE_ERROR, self.message.clone(), self.token.span);
+
+        match (self.declared_at, self.parameter) {
+            (Some(span), true) => diagnostic
+                .with_secondary_label(span, "constant parameter declared here".to_string())
+                .with_help("remove 'const' from the parameter to allow assignment".to_string()),
+            (Some(span), false) => diagnostic
+                .with_secondary_label(span, "constant declared here".to_string())
+                .with_help("declare it with 'var' to allow assignment".to_string()),
+            (None, _) => diagnostic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    UnusedVariable,
+    UnreachableCode,
+    Shadowing,
+    ConstantCondition,
+}
+
+impl WarningKind {
+    // The name used to allow it in a `// lox-allow: name` comment
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => "unused-variable",
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::Shadowing => "shadowing",
+            WarningKind::ConstantCondition => "constant-condition",
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => UNUSED_VARIABLE,
+            WarningKind::UnreachableCode => UNREACHABLE_CODE,
+            WarningKind::Shadowing => SHADOWING,
+            WarningKind::ConstantCondition => CONSTANT_CONDITION,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    pub span: Span,
+    // Another place involved, like the declaration being shadowed
+    pub related: Option<(Span, String)>,
+}
+
+impl Warning {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::warning(self.kind.code(), self.message.clone(), self.span)
+            .with_note(format!(
+                "add `// lox-allow: {}` to the line to silence this",
+                self.kind.name()
+            ));
+
+        match &self.related {
+            Some((span, message)) => diagnostic.with_secondary_label(*span, message.clone()),
+            None => diagnostic,
+        }
+    }
+
+    // A `// lox-allow` comment on the line where the warning starts silences
+    // every warning there, `// lox-allow: shadowing, unused-variable` only those
+    pub fn is_allowed(&self, source: &str, comments: &[Comment]) -> bool {
+        let (line, _) = position(source, self.span.start);
+
+        comments
+            .iter()
+            .filter(|comment| comment.line as usize == line)
+            .any(
+                |comment| match comment.text.trim().strip_prefix("lox-allow") {
+                    Some(directive) => match directive.trim().strip_prefix(':') {
+                        Some(names) => names.split(',').any(|name| name.trim() == self.kind.name()),
+                        None => directive.trim().is_empty(),
+                    },
+                    None => false,
+                },
+            )
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            scopes: vec![HashMap::new()],
+            function_depth: 0,
+            errors: vec![],
+            warnings: vec![],
+        }
+    }
+
+    // Warnings come back in source order
+    pub fn resolve(&mut self, statements: &[Stmt]) -> (Vec<ResolverError>, Vec<Warning>) {
+        self.resolve_statements(statements);
+
+        let mut warnings = self.warnings.clone();
+        warnings.sort_by_key(|warning| warning.span.start);
+
+        (self.errors.clone(), warnings)
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        self.resolve_block(statements, None)
+    }
+
+    // The statements of a block, followed by its value if it is a block expression
+    fn resolve_block(&mut self, statements: &[Stmt], value: Option<&Expr>) {
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+
+        if let Some(value_expr) = value {
+            self.resolve_expr(value_expr);
+        }
+
+        let returns = statements
+            .iter()
+            .position(|statement| matches!(statement, Stmt::Return { .. }));
+
+        if let Some(index) = returns {
+            let first = match statements.get(index + 1) {
+                Some(statement) => Some(statement.span()),
+                None => value.map(|value_expr| value_expr.span()),
+            };
+            let last = match value {
+                Some(value_expr) => Some(value_expr.span()),
+                None => statements.last().map(|statement| statement.span()),
+            };
+
+            if let (Some(first), Some(last)) = (first, last) {
+                self.warn(
+                    WarningKind::UnreachableCode,
+                    "Unreachable code.".to_string(),
+                    first.to(last),
+                    Some((
+                        statements[index].span(),
+                        "any code after this return is unreachable".to_string(),
+                    )),
+                );
+            }
+        }
+    }
+
+    // `if (true)` or `while (nil)` is almost certainly left over from debugging
+    fn check_condition(&mut self, condition: &Expr) {
+        let mut condition = condition;
+        while let Expr::Grouping {
+            expression,
+            span: _,
+        } = condition
+        {
+            condition = expression;
+        }
+
+        if let Expr::Literal { value, span } = condition {
+            // A `for` loop without a condition is given an implicit `true`, which
+            // has no source and so an empty span
+            if span.start == span.end {
+                return;
+            }
+
+            let truthy = !matches!(value, Value::Nil | Value::Boolean { value: false });
+
+            self.warn(
+                WarningKind::ConstantCondition,
+                format!("This condition is always {}.", truthy),
+                *span,
+                None,
+            );
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        expr.accept(self)
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    // Names starting with an underscore are meant to go unused
+    fn end_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            for binding in scope.values() {
+                if binding.used || binding.name.lexeme.starts_with('_') {
+                    continue;
+                }
+
+                let what = match binding.kind {
+                    BindingKind::Variable => "variable",
+                    BindingKind::Parameter => "parameter",
+                    BindingKind::Declaration => continue,
+                };
+
+                self.warn(
+                    WarningKind::UnusedVariable,
+                    format!("Unused {} '{}'.", what, binding.name.lexeme),
+                    binding.name.span,
+                    None,
+                );
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token, kind: BindingKind, constant: bool) {
+        // Globals can't shadow anything, and redeclaring a name in the same scope
+        // replaces it rather than shadowing it
+        let current = self.scopes.len() - 1;
+        let shadowed = self.scopes[..current]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+            .map(|binding| binding.name.span);
+
+        if let Some(declared_at) = shadowed {
+            if !self.scopes[current].contains_key(&name.lexeme) {
+                self.warn(
+                    WarningKind::Shadowing,
+                    format!("'{}' shadows an outer declaration.", name.lexeme),
+                    name.span,
+                    Some((declared_at, "shadowed declaration".to_string())),
+                );
+            }
+        }
+
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.lexeme.to_string(),
+                Binding {
+                    name: name.clone(),
+                    kind,
+                    constant,
+                    used: false,
+                },
+            );
+        }
+    }
+
+    fn mark_used(&mut self, name: &Token) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.get_mut(&name.lexeme) {
+                binding.used = true;
+                return;
+            }
+        }
+    }
+
+    // Only the innermost declaration of a name matters, so stop at the first
+    // scope that knows about it.
+    // The constant a name refers to, if it does refer to one
+    fn constant_declaration(&self, name: &Token) -> Option<&Binding> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(binding) = scope.get(&name.lexeme) {
+                return if binding.constant {
+                    Some(binding)
+                } else {
+                    None
+                };
+            }
+        }
+
+        None
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.errors.push(ResolverError {
+            token: token.clone(),
+            message,
+            declared_at: None,
+            parameter: false,
+        });
+    }
+
+    fn warn(
+        &mut self,
+        kind: WarningKind,
+        message: String,
+        span: Span,
+        related: Option<(Span, String)>,
+    ) {
+        self.warnings.push(Warning {
+            kind,
+            message,
+            span,
+            related,
+        });
+    }
+
+    fn check_not_constant(&mut self, name: &Token) {
+        if let Some(binding) = self.constant_declaration(name) {
+            let error = ResolverError {
+                token: name.clone(),
+                message: format!("Cannot assign to constant '{}'", name.lexeme),
+                declared_at: Some(binding.name.span),
+                parameter: binding.kind == BindingKind::Parameter,
+            };
+            self.errors.push(error);
+        }
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_assign_expr(&mut self, expr: &Expr) {
+        if let Expr::Assign {
+            name,
+            value,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(value);
+
+            self.check_not_constant(name);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_binary_expr(&mut self, expr: &Expr) {
+        if let Expr::Binary {
+            left,
+            operator: _,
+            right,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(left);
+            self.resolve_expr(right);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &Expr) {
+        if let Expr::Grouping {
+            expression,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(expression);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_literal_expr(&mut self, _expr: &Expr) {}
+
+    fn visit_unary_expr(&mut self, expr: &Expr) {
+        if let Expr::Unary {
+            operator: _,
+            right,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(right);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_variable_expr(&mut self, expr: &Expr) {
+        if let Expr::Variable { name, span: _ } = expr {
+            self.mark_used(name);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_logical_expr(&mut self, expr: &Expr) {
+        if let Expr::Logical {
+            left,
+            operator: _,
+            right,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(left);
+            self.resolve_expr(right);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_call_expr(&mut self, expr: &Expr) {
+        if let Expr::Call {
+            callee,
+            paren: _,
+            arguments,
+            optional: _,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(callee);
+
+            for argument in arguments.iter() {
+                self.resolve_expr(argument);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_get_expr(&mut self, expr: &Expr) {
+        if let Expr::Get {
+            object,
+            name: _,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(object);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_index_expr(&mut self, expr: &Expr) {
+        if let Expr::Index {
+            object,
+            bracket: _,
+            index,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(object);
+            self.resolve_expr(index);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_slice_expr(&mut self, expr: &Expr) {
+        if let Expr::Slice {
+            object,
+            bracket: _,
+            start,
+            end,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(object);
+
+            if let Some(start_expr) = start {
+                self.resolve_expr(start_expr);
+            }
+            if let Some(end_expr) = end {
+                self.resolve_expr(end_expr);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_tuple_expr(&mut self, expr: &Expr) {
+        if let Expr::Tuple { elements, span: _ } = expr {
+            for element in elements.iter() {
+                self.resolve_expr(element);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_if_expr(&mut self, expr: &Expr) {
+        if let Expr::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: _,
+        } = expr
+        {
+            self.resolve_expr(condition);
+            self.check_condition(condition);
+            self.resolve_expr(then_branch);
+
+            if let Some(else_expr) = else_branch {
+                self.resolve_expr(else_expr);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_block_expr(&mut self, expr: &Expr) {
+        if let Expr::Block {
+            statements,
+            value,
+            span: _,
+        } = expr
+        {
+            self.begin_scope();
+            self.resolve_block(statements, value.as_deref());
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_expression_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Expression { expr, span: _ } = stmt {
+            self.resolve_expr(expr);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Print { expr, span: _ } = stmt {
+            self.resolve_expr(expr);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_variable_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Var {
+            name,
+            initializer,
+            constant,
+            type_annotation: _,
+            span: _,
+        } = stmt
+        {
+            if let Some(initializer_expression) = initializer {
+                self.resolve_expr(initializer_expression);
+            }
+
+            self.declare(name, BindingKind::Variable, *constant);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_block_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Block {
+            statements,
+            span: _,
+        } = stmt
+        {
+            self.begin_scope();
+            self.resolve_statements(statements);
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: _,
+        } = stmt
+        {
+            self.resolve_expr(condition);
+            self.check_condition(condition);
+            then_branch.accept(self);
+
+            if let Some(else_stmt) = else_branch {
+                else_stmt.accept(self);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::While {
+            condition,
+            body,
+            span: _,
+        } = stmt
+        {
+            self.resolve_expr(condition);
+            self.check_condition(condition);
+            body.accept(self);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Function {
+            name,
+            params,
+            body,
+            decorators,
+            return_type: _,
+            span: _,
+        } = stmt
+        {
+            for decorator in decorators.iter() {
+                self.resolve_expr(decorator);
+            }
+
+            self.declare(name, BindingKind::Declaration, false);
+
+            self.begin_scope();
+            self.function_depth += 1;
+            for param in params.iter() {
+                self.declare(&param.name, BindingKind::Parameter, param.constant);
+            }
+            self.resolve_statements(body);
+            self.function_depth -= 1;
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Return {
+            keyword,
+            value,
+            span: _,
+        } = stmt
+        {
+            if self.function_depth == 0 {
+                self.error(keyword, "Can't return from top-level code.".to_string());
+            }
+
+            self.resolve_expr(value);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_assign_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Assign {
+            names,
+            values,
+            span: _,
+        } = stmt
+        {
+            for value in values.iter() {
+                self.resolve_expr(value);
+            }
+
+            for name in names.iter() {
+                self.check_not_constant(name);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_unpack_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Unpack {
+            names,
+            paren: _,
+            initializer,
+            constant,
+            span: _,
+        } = stmt
+        {
+            self.resolve_expr(initializer);
+
+            for name in names.iter() {
+                self.declare(name, BindingKind::Variable, *constant);
+            }
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_enum_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Enum {
+            name,
+            variants: _,
+            span: _,
+        } = stmt
+        {
+            self.declare(name, BindingKind::Declaration, false);
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::ForIn {
+            name,
+            iterable,
+            body,
+            span: _,
+        } = stmt
+        {
+            self.resolve_expr(iterable);
+
+            self.begin_scope();
+            self.declare(name, BindingKind::Variable, false);
+            body.accept(self);
+            self.end_scope();
+        } else {
+            panic!("Nope!")
+        }
+    }
+
+    fn visit_defer_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Defer {
+            keyword,
+            stmt,
+            span: _,
+        } = stmt
+        {
+            // Only the global scope has no block around it to run the defer on exit
+            if self.scopes.len() == 1 {
+                self.error(keyword, "Can't use 'defer' outside of a block.".to_string());
+            }
+
+            stmt.accept(self);
+        } else {
+            panic!("Nope!")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        parser::Parser,
+        token::{Scanner, Span},
+    };
+
+    use super::{Resolver, ResolverError, Warning, WarningKind};
+
+    fn resolve(source: &str) -> Vec<ResolverError> {
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        Resolver::new().resolve(&statements).0
+    }
+
+    fn warnings(source: &str) -> Vec<Warning> {
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+        Resolver::new().resolve(&statements).1
+    }
+
+    #[test]
+    fn it_reports_assignments_to_constants() {
+        let errors = resolve("const a = 1;\na = 2;");
+
+        assert_eq!(1, errors.len());
+        assert_eq!(2, errors[0].token.line);
+        assert_eq!("Cannot assign to constant 'a'", errors[0].message);
+        assert_eq!(Some(Span::new(6, 7)), errors[0].declared_at);
+        assert_eq!(
+            vec!["declare it with 'var' to allow assignment".to_string()],
+            errors[0].diagnostic().help
+        );
+    }
+
+    #[test]
+    fn it_reports_assignments_to_constant_parameters() {
+        let errors = resolve("fun f(const a, b) { b = 1; a = 2; }");
+
+        assert_eq!(1, errors.len());
+        assert_eq!("a", errors[0].token.lexeme);
+        assert_eq!(
+            vec!["remove 'const' from the parameter to allow assignment".to_string()],
+            errors[0].diagnostic().help
+        );
+    }
+
+    #[test]
+    fn it_allows_assigning_a_shadowing_variable() {
+        let errors = resolve("const a = 1; { var a = 2; a = 3; }");
+
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn it_reports_a_return_outside_of_a_function() {
+        let errors = resolve("fun f() { return 1; }\nreturn 2;");
+
+        assert_eq!(1, errors.len());
+        assert_eq!(2, errors[0].token.line);
+        assert_eq!("Can't return from top-level code.", errors[0].message);
+    }
+
+    #[test]
+    fn it_reports_defer_outside_of_a_block() {
+        let errors = resolve("defer print 1;\n{ defer print 2; }");
+
+        assert_eq!(1, errors.len());
+        assert_eq!(1, errors[0].token.line);
+        assert_eq!("Can't use 'defer' outside of a block.", errors[0].message);
+    }
+
+    #[test]
+    fn it_warns_about_unused_locals_and_parameters() {
+        let warnings = warnings(
+            "var global = 1;\nfun f(a, b, _c) {\n  var d = 1;\n  var e = 2;\n  e = 3;\n  return a;\n}",
+        );
+
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            vec![
+                "Unused parameter 'b'.",
+                "Unused variable 'd'.",
+                "Unused variable 'e'."
+            ],
+            messages
+        );
+        assert!(warnings
+            .iter()
+            .all(|w| w.kind == WarningKind::UnusedVariable));
+    }
+
+    #[test]
+    fn it_warns_about_code_after_a_return() {
+        let warnings = warnings("fun f() {\n  return 1;\n  print 2;\n  print 3;\n}\nf();");
+
+        assert_eq!(1, warnings.len());
+        assert_eq!(WarningKind::UnreachableCode, warnings[0].kind);
+        assert_eq!(Span::new(24, 42), warnings[0].span);
+    }
+
+    #[test]
+    fn it_warns_about_a_block_value_after_a_return() {
+        let warnings = warnings("fun f() {\n  print { return 1; 2 };\n}\nf();");
+
+        assert_eq!(1, warnings.len());
+        assert_eq!(WarningKind::UnreachableCode, warnings[0].kind);
+        assert_eq!(Span::new(30, 31), warnings[0].span);
+    }
+
+    #[test]
+    fn it_warns_about_shadowing_an_outer_declaration() {
+        let warnings = warnings("var a = 1;\n{ var a = 2; print a; }\nvar a = 3;");
+
+        assert_eq!(1, warnings.len());
+        assert_eq!(WarningKind::Shadowing, warnings[0].kind);
+        assert_eq!(
+            Some(Span::new(4, 5)),
+            warnings[0].related.as_ref().map(|r| r.0)
+        );
+    }
+
+    #[test]
+    fn it_warns_about_constant_conditions_but_not_a_bare_for() {
+        let warnings = warnings(
+            "if (true) print 1;\nwhile ((nil)) print 2;\nfor (;;) print 3;\nif (clock()) print 4;",
+        );
+
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            vec![
+                "This condition is always true.",
+                "This condition is always false."
+            ],
+            messages
+        );
+    }
+
+    fn allowed(source: &str) -> Vec<bool> {
+        let mut scanner = Scanner::new(source);
+        let (tokens, _) = scanner.scan_tokens();
+        let (statements, _) = Parser::new(&tokens).parse();
+
+        Resolver::new()
+            .resolve(&statements)
+            .1
+            .iter()
+            .map(|warning: &Warning| warning.is_allowed(source, scanner.comments()))
+            .collect()
+    }
+
+    #[test]
+    fn it_lets_a_comment_allow_warnings_on_its_line() {
+        let source = "{\n  var a = 1; // lox-allow\n  var b = 2; // lox-allow: unused-variable\n  var c = 3; // lox-allow: shadowing\n}";
+
+        assert_eq!(vec![true, true, false], allowed(source));
+    }
+
+    #[test]
+    fn it_ignores_allow_directives_inside_strings() {
+        let source = "{\n  var a = \"// lox-allow\";\n}";
+
+        assert_eq!(vec![false], allowed(source));
+    }
+}
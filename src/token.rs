@@ -3,12 +3,13 @@ use lazy_static::lazy_static;
 use std::{borrow::Borrow, collections::HashMap, fmt::Debug, rc::Rc};
 
 use crate::{
+    diagnostic::{Diagnostic, SCAN_ERROR},
     environment::Environment,
-    expression::Stmt,
+    expression::{EnumVariant, Stmt},
     interpreter::{ExpressionResult, Interpreter, InterpreterError},
 };
 
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TokenType {
     // Single-character tokens.
@@ -16,13 +17,21 @@ pub enum TokenType {
     RIGHT_PAREN,
     LEFT_BRACE,
     RIGHT_BRACE,
+    LEFT_BRACKET,
+    RIGHT_BRACKET,
     COMMA,
     DOT,
     MINUS,
     PLUS,
     SEMICOLON,
+    COLON,
     SLASH,
     STAR,
+    AMPERSAND,
+    PIPE,
+    CARET,
+    TILDE,
+    AT,
     // One or two character tokens.
     BANG,
     BANG_EQUAL,
@@ -32,6 +41,13 @@ pub enum TokenType {
     GREATER_EQUAL,
     LESS,
     LESS_EQUAL,
+    LESS_LESS,
+    GREATER_GREATER,
+    QUESTION_QUESTION,
+    QUESTION_DOT,
+    PIPE_GREATER,
+    DOT_DOT,
+    DOT_DOT_EQUAL,
     // Literals.
     IDENTIFIER,
     STRING,
@@ -39,11 +55,15 @@ pub enum TokenType {
     // Keywords.
     AND,
     CLASS,
+    CONST,
+    DEFER,
     ELSE,
+    ENUM,
     FALSE,
     FUN,
     FOR,
     IF,
+    IN,
     NIL,
     OR,
     PRINT,
@@ -60,11 +80,15 @@ lazy_static! {
     static ref KEYWORDS: HashMap<&'static str, TokenType> = HashMap::from([
         ("and", TokenType::AND),
         ("class", TokenType::CLASS),
+        ("const", TokenType::CONST),
+        ("defer", TokenType::DEFER),
         ("else", TokenType::ELSE),
+        ("enum", TokenType::ENUM),
         ("false", TokenType::FALSE),
         ("for", TokenType::FOR),
         ("fun", TokenType::FUN),
         ("if", TokenType::IF),
+        ("in", TokenType::IN),
         ("nil", TokenType::NIL),
         ("or", TokenType::OR),
         ("print", TokenType::PRINT),
@@ -85,13 +109,21 @@ impl fmt::Display for TokenType {
             TokenType::RIGHT_PAREN => write!(f, "RIGHT_PAREN"),
             TokenType::LEFT_BRACE => write!(f, "LEFT_BRACE"),
             TokenType::RIGHT_BRACE => write!(f, "RIGHT_BRACE"),
+            TokenType::LEFT_BRACKET => write!(f, "LEFT_BRACKET"),
+            TokenType::RIGHT_BRACKET => write!(f, "RIGHT_BRACKET"),
             TokenType::COMMA => write!(f, "COMMA"),
             TokenType::DOT => write!(f, "DOT"),
             TokenType::MINUS => write!(f, "MINUS"),
             TokenType::PLUS => write!(f, "PLUS"),
             TokenType::SEMICOLON => write!(f, "SEMICOLON"),
+            TokenType::COLON => write!(f, "COLON"),
             TokenType::SLASH => write!(f, "SLASH"),
             TokenType::STAR => write!(f, "STAR"),
+            TokenType::AMPERSAND => write!(f, "AMPERSAND"),
+            TokenType::PIPE => write!(f, "PIPE"),
+            TokenType::CARET => write!(f, "CARET"),
+            TokenType::TILDE => write!(f, "TILDE"),
+            TokenType::AT => write!(f, "AT"),
             // One or two character tokens.
             TokenType::BANG => write!(f, "BANG"),
             TokenType::BANG_EQUAL => write!(f, "BANG_EQUAL"),
@@ -101,6 +133,13 @@ impl fmt::Display for TokenType {
             TokenType::GREATER_EQUAL => write!(f, "GREATER_EQUAL"),
             TokenType::LESS => write!(f, "LESS"),
             TokenType::LESS_EQUAL => write!(f, "LESS_EQUAL"),
+            TokenType::LESS_LESS => write!(f, "LESS_LESS"),
+            TokenType::GREATER_GREATER => write!(f, "GREATER_GREATER"),
+            TokenType::QUESTION_QUESTION => write!(f, "QUESTION_QUESTION"),
+            TokenType::QUESTION_DOT => write!(f, "QUESTION_DOT"),
+            TokenType::PIPE_GREATER => write!(f, "PIPE_GREATER"),
+            TokenType::DOT_DOT => write!(f, "DOT_DOT"),
+            TokenType::DOT_DOT_EQUAL => write!(f, "DOT_DOT_EQUAL"),
             // Literals.
             TokenType::IDENTIFIER => write!(f, "IDENTIFIER"),
             TokenType::STRING => write!(f, "STRING"),
@@ -108,11 +147,15 @@ impl fmt::Display for TokenType {
             // Keywords.
             TokenType::AND => write!(f, "AND"),
             TokenType::CLASS => write!(f, "CLASS"),
+            TokenType::CONST => write!(f, "CONST"),
+            TokenType::DEFER => write!(f, "DEFER"),
             TokenType::ELSE => write!(f, "ELSE"),
+            TokenType::ENUM => write!(f, "ENUM"),
             TokenType::FALSE => write!(f, "FALSE"),
             TokenType::FUN => write!(f, "FUN"),
             TokenType::FOR => write!(f, "FOR"),
             TokenType::IF => write!(f, "IF"),
+            TokenType::IN => write!(f, "IN"),
             TokenType::NIL => write!(f, "NIL"),
             TokenType::OR => write!(f, "OR"),
             TokenType::PRINT => write!(f, "PRINT"),
@@ -130,24 +173,32 @@ impl fmt::Display for TokenType {
 #[derive(Clone, Debug)]
 pub enum Callable {
     NativeFunction {
+        name: String,
         arity: i8,
-        call: fn(&Interpreter, &Vec<Value>) -> ExpressionResult,
+        call: fn(&Interpreter, &Token, &Vec<Value>) -> ExpressionResult,
         value: String,
     },
     Function {
         declaration: Box<Stmt>,
         closure: Rc<Environment>,
     },
+    // Builds a variant of an enum from its payload, e.g. `Shape.Circle(2)`
+    EnumConstructor {
+        enum_name: String,
+        variant: String,
+        fields: Vec<String>,
+    },
 }
 
 impl Callable {
     pub fn arity(&self) -> i8 {
         match self {
             Callable::NativeFunction {
+                name: _,
                 arity,
                 call: _,
                 value: _,
-            } => arity.clone(),
+            } => *arity,
             Callable::Function {
                 declaration,
                 closure: _,
@@ -156,6 +207,9 @@ impl Callable {
                     name: _,
                     params,
                     body: _,
+                    decorators: _,
+                    return_type: _,
+                    span: _,
                 } = declaration.as_ref()
                 {
                     params.len() as i8
@@ -163,52 +217,153 @@ impl Callable {
                     panic!("No params")
                 }
             }
+            Callable::EnumConstructor {
+                enum_name: _,
+                variant: _,
+                fields,
+            } => fields.len() as i8,
         }
     }
 
-    pub fn call(&self, interpreter: &mut Interpreter, values: &Vec<Value>) -> ExpressionResult {
+    // What to call this in messages: the same as `value`, except that natives
+    // use their own name
+    pub fn name(&self) -> String {
         match self {
             Callable::NativeFunction {
+                name,
+                arity: _,
+                call: _,
+                value: _,
+            } => name.clone(),
+            _ => self.value(),
+        }
+    }
+
+    // `paren` is the call's closing parenthesis, which errors raised by the call are reported at
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        values: &Vec<Value>,
+    ) -> ExpressionResult {
+        match self {
+            Callable::NativeFunction {
+                name,
                 arity: _,
                 call,
                 value: _,
-            } => call(interpreter, values),
+            } => {
+                interpreter.push_frame(format!("[native {}]", name), true, paren.line);
+                let result = call(interpreter, paren, values);
+                interpreter.pop_frame(&result);
+                result
+            }
             Callable::Function {
-                declaration,
-                closure,
+                declaration: _,
+                closure: _,
             } => {
-                if let Stmt::Function {
-                    name: _,
-                    params,
-                    body,
-                } = declaration.as_ref()
-                {
-                    let environment = Environment::new(Some(Rc::clone(&closure)));
+                // A call in tail position comes back to us as a TailCall instead of
+                // being made from inside the body, so we loop here rather than
+                // growing the Rust stack once per Lox call. The tail call reuses
+                // this call's frame in the traceback.
+                let mut callable = self.clone();
+                let mut arguments = values.clone();
 
-                    for (i, param) in params.iter().enumerate() {
-                        environment.define(param, &values[i]);
-                    }
+                interpreter.push_frame(self.value(), false, paren.line);
+                let deferred_base = interpreter.enter_function();
 
-                    // TODO Handle return
-                    match interpreter.execute_block(body, environment) {
-                        Ok(_) => Ok(Value::Nil),
-                        Err(err) => match err {
-                            InterpreterError::RuntimeError { operator, error } => {
-                                Err(InterpreterError::RuntimeError { operator, error })
+                let result = loop {
+                    let result = if let Callable::Function {
+                        declaration,
+                        closure,
+                    } = &callable
+                    {
+                        if let Stmt::Function {
+                            name: _,
+                            params,
+                            body,
+                            decorators: _,
+                            return_type: _,
+                            span: _,
+                        } = declaration.as_ref()
+                        {
+                            let environment = Environment::new(Some(Rc::clone(closure)));
+
+                            for (i, param) in params.iter().enumerate() {
+                                if param.constant {
+                                    environment.define_constant(&param.name, &arguments[i]);
+                                } else {
+                                    environment.define(&param.name, &arguments[i]);
+                                }
                             }
-                            InterpreterError::Return { value } => Ok(value),
+
+                            interpreter.execute_block(body, environment)
+                        } else {
+                            panic!("Nope!")
+                        }
+                    } else {
+                        panic!("Nope!")
+                    };
+
+                    match result {
+                        Ok(_) => break Ok(Value::Nil),
+                        Err(err) => match err {
+                            InterpreterError::Return { value } => break Ok(value),
+                            InterpreterError::TailCall {
+                                callee,
+                                paren: tail_paren,
+                                arguments: tail_arguments,
+                            } => match *callee {
+                                Value::Callable {
+                                    callable:
+                                        tail_callable @ Callable::Function {
+                                            declaration: _,
+                                            closure: _,
+                                        },
+                                } => {
+                                    interpreter.replace_frame(tail_callable.value());
+                                    callable = tail_callable;
+                                    arguments = tail_arguments;
+                                }
+                                // Only Lox functions can loop here, anything else is
+                                // called as usual from inside this frame
+                                Value::Callable {
+                                    callable: tail_callable,
+                                } => {
+                                    break tail_callable.call(
+                                        interpreter,
+                                        &tail_paren,
+                                        &tail_arguments,
+                                    )
+                                }
+                                _ => panic!("Tail call to a non-callable value"),
+                            },
+                            err => break Err(err),
                         },
                     }
-                } else {
-                    panic!("Nope!")
-                }
+                };
+
+                interpreter.leave_function(deferred_base);
+                interpreter.pop_frame(&result);
+                result
             }
+            Callable::EnumConstructor {
+                enum_name,
+                variant,
+                fields,
+            } => Ok(Value::Variant {
+                enum_name: enum_name.clone(),
+                variant: variant.clone(),
+                fields: fields.clone(),
+                values: values.clone(),
+            }),
         }
     }
 
     pub fn value(&self) -> String {
         match self {
             Callable::NativeFunction {
+                name: _,
                 arity: _,
                 call: _,
                 value,
@@ -221,24 +376,62 @@ impl Callable {
                     name,
                     params: _,
                     body: _,
+                    decorators: _,
+                    return_type: _,
+                    span: _,
                 } = declaration.as_ref()
                 {
-                    name.to_string()
+                    name.lexeme.clone()
                 } else {
                     panic!("Nope!")
                 }
             }
+            Callable::EnumConstructor {
+                enum_name,
+                variant,
+                fields: _,
+            } => format!("{}.{}", enum_name, variant),
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum Value {
-    Boolean { value: bool },
-    Double { value: f64 },
-    String { value: String },
+    Boolean {
+        value: bool,
+    },
+    Double {
+        value: f64,
+    },
+    String {
+        value: String,
+    },
     Nil,
-    Callable { callable: Callable },
+    Callable {
+        callable: Callable,
+    },
+    Tuple {
+        values: Vec<Value>,
+    },
+    // The enum itself, whose variants are looked up with `.`
+    Enum {
+        name: String,
+        variants: Rc<Vec<EnumVariant>>,
+    },
+    // A lazy sequence of integers, `start..end` or `start..=end`
+    Range {
+        start: i64,
+        end: i64,
+        step: i64,
+        inclusive: bool,
+    },
+    // A tagged value built from one of an enum's variants
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<String>,
+        values: Vec<Value>,
+    },
 }
 
 impl fmt::Display for Value {
@@ -247,8 +440,49 @@ impl fmt::Display for Value {
             Value::Boolean { value } => f.write_str(&value.to_string()),
             Value::Double { value } => f.write_str(&value.to_string()),
             Value::String { value } => f.write_str(&value.to_string()),
-            Value::Nil => f.write_str(&"Nil".to_string()),
+            Value::Nil => f.write_str("Nil"),
             Value::Callable { callable } => f.write_str(&callable.value()),
+            Value::Tuple { values } => {
+                let elements: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+
+                if elements.len() == 1 {
+                    write!(f, "({},)", elements[0])
+                } else {
+                    write!(f, "({})", elements.join(", "))
+                }
+            }
+            Value::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let operator = if *inclusive { "..=" } else { ".." };
+                write!(f, "{}{}{}", start, operator, end)?;
+
+                if *step != 1 {
+                    write!(f, " step {}", step)?;
+                }
+
+                Ok(())
+            }
+            Value::Enum { name, variants: _ } => f.write_str(name),
+            Value::Variant {
+                enum_name,
+                variant,
+                fields: _,
+                values,
+            } => {
+                write!(f, "{}.{}", enum_name, variant)?;
+
+                if !values.is_empty() {
+                    let elements: Vec<String> =
+                        values.iter().map(|value| value.to_string()).collect();
+                    write!(f, "({})", elements.join(", "))?;
+                }
+
+                Ok(())
+            }
         }
     }
 }
@@ -260,6 +494,36 @@ impl PartialEq for Value {
             (Value::Double { value: v1 }, Value::Double { value: v2 }) => v1 == v2,
             (Value::String { value: v1 }, Value::String { value: v2 }) => v1 == v2,
             (Value::Nil, Value::Nil) => true,
+            (Value::Tuple { values: v1 }, Value::Tuple { values: v2 }) => v1 == v2,
+            (Value::Enum { name: n1, .. }, Value::Enum { name: n2, .. }) => n1 == n2,
+            (
+                Value::Range {
+                    start: s1,
+                    end: e1,
+                    step: st1,
+                    inclusive: i1,
+                },
+                Value::Range {
+                    start: s2,
+                    end: e2,
+                    step: st2,
+                    inclusive: i2,
+                },
+            ) => s1 == s2 && e1 == e2 && st1 == st2 && i1 == i2,
+            (
+                Value::Variant {
+                    enum_name: e1,
+                    variant: t1,
+                    values: v1,
+                    ..
+                },
+                Value::Variant {
+                    enum_name: e2,
+                    variant: t2,
+                    values: v2,
+                    ..
+                },
+            ) => e1 == e2 && t1 == t2 && v1 == v2,
             // TODO proper implemenentaion for Callable
             // For now, just assume that comparing two Callables should return false
             (Value::Callable { callable: _c1 }, Value::Callable { callable: _c2 }) => false,
@@ -274,9 +538,34 @@ pub struct Token {
     pub(crate) lexeme: String,
     pub(crate) literal: Option<Value>,
     pub(crate) line: i64,
+    // 1-based, counted in characters from the start of the token's first line
+    pub(crate) column: i64,
+    pub(crate) span: Span,
+}
+
+// A range of byte offsets into the source, `end` being exclusive
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // The smallest span covering both `self` and `other`
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
 }
 
 impl fmt::Display for Token {
+    #[allow(clippy::single_match)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut debug_string = self.ttype.to_string() + " " + &self.lexeme;
 
@@ -289,44 +578,99 @@ impl fmt::Display for Token {
     }
 }
 
-pub struct Scanner<'a> {
-    source: &'a str,
+pub struct Scanner {
+    // Indexed by character rather than byte so that non-ASCII source scans correctly
+    source: Vec<char>,
+    // The byte offset of each character in `source`, plus one for the end
+    offsets: Vec<usize>,
     start: i64,
     current: i64,
     line: i64,
+    // Where the current line begins, so columns don't need to scan back for it
+    line_start: i64,
+    // The line and column of `start`, which a token spanning lines is reported at
+    start_line: i64,
+    start_column: i64,
     tokens: Vec<Token>,
+    errors: Vec<ScanError>,
+    comments: Vec<Comment>,
+}
+
+// A `//` comment, kept so that directives like `// lox-allow` can be found
+// without mistaking the same text inside a string for one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub line: i64,
+    // Everything after the `//`
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub line: i64,
+    pub column: i64,
+    pub message: String,
+    pub span: Span,
+}
+
+impl ScanError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(SCAN_ERROR, self.message.clone(), self.span)
+    }
 }
 
-impl<'a> Scanner<'a> {
+impl Scanner {
     pub fn new(source: &str) -> Scanner {
+        let mut offsets: Vec<usize> = source.char_indices().map(|(i, _)| i).collect();
+        offsets.push(source.len());
+
         Scanner {
-            source: source,
+            source: source.chars().collect(),
+            offsets,
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             tokens: vec![],
+            errors: vec![],
+            comments: vec![],
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
+    // Scans the whole source. Like the parser, we keep going after an error so
+    // that they can all be reported together.
+    pub fn scan_tokens(&mut self) -> (Vec<Token>, Vec<ScanError>) {
         loop {
             if self.is_at_end() {
                 break;
             }
 
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.current - self.line_start + 1;
             // scan token
             self.scan_token();
         }
 
+        let end = self.offsets[self.current as usize];
+
         self.tokens.push(Token {
             ttype: TokenType::EOF,
             lexeme: "".to_string(),
             literal: None,
             line: self.line,
+            column: self.current - self.line_start + 1,
+            span: Span::new(end, end),
         });
 
-        self.tokens.clone()
+        (self.tokens.clone(), self.errors.clone())
+    }
+
+    // The comments passed over by `scan_tokens`
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
     }
 
     fn scan_token(&mut self) {
@@ -337,12 +681,36 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token_no_literal(TokenType::RIGHT_PAREN),
             '{' => self.add_token_no_literal(TokenType::LEFT_BRACE),
             '}' => self.add_token_no_literal(TokenType::RIGHT_BRACE),
+            '[' => self.add_token_no_literal(TokenType::LEFT_BRACKET),
+            ']' => self.add_token_no_literal(TokenType::RIGHT_BRACKET),
             ',' => self.add_token_no_literal(TokenType::COMMA),
-            '.' => self.add_token_no_literal(TokenType::DOT),
+            '.' => {
+                if self.is_match('.') {
+                    if self.is_match('=') {
+                        self.add_token_no_literal(TokenType::DOT_DOT_EQUAL);
+                    } else {
+                        self.add_token_no_literal(TokenType::DOT_DOT);
+                    }
+                } else {
+                    self.add_token_no_literal(TokenType::DOT);
+                }
+            }
             '-' => self.add_token_no_literal(TokenType::MINUS),
             '+' => self.add_token_no_literal(TokenType::PLUS),
             ';' => self.add_token_no_literal(TokenType::SEMICOLON),
+            ':' => self.add_token_no_literal(TokenType::COLON),
             '*' => self.add_token_no_literal(TokenType::STAR),
+            '&' => self.add_token_no_literal(TokenType::AMPERSAND),
+            '|' => {
+                if self.is_match('>') {
+                    self.add_token_no_literal(TokenType::PIPE_GREATER);
+                } else {
+                    self.add_token_no_literal(TokenType::PIPE);
+                }
+            }
+            '^' => self.add_token_no_literal(TokenType::CARET),
+            '~' => self.add_token_no_literal(TokenType::TILDE),
+            '@' => self.add_token_no_literal(TokenType::AT),
             '!' => {
                 if self.is_match('=') {
                     self.add_token_no_literal(TokenType::BANG_EQUAL);
@@ -360,6 +728,8 @@ impl<'a> Scanner<'a> {
             '<' => {
                 if self.is_match('=') {
                     self.add_token_no_literal(TokenType::LESS_EQUAL);
+                } else if self.is_match('<') {
+                    self.add_token_no_literal(TokenType::LESS_LESS);
                 } else {
                     self.add_token_no_literal(TokenType::LESS);
                 }
@@ -367,10 +737,21 @@ impl<'a> Scanner<'a> {
             '>' => {
                 if self.is_match('=') {
                     self.add_token_no_literal(TokenType::GREATER_EQUAL);
+                } else if self.is_match('>') {
+                    self.add_token_no_literal(TokenType::GREATER_GREATER);
                 } else {
                     self.add_token_no_literal(TokenType::GREATER);
                 }
             }
+            '?' => {
+                if self.is_match('?') {
+                    self.add_token_no_literal(TokenType::QUESTION_QUESTION);
+                } else if self.is_match('.') {
+                    self.add_token_no_literal(TokenType::QUESTION_DOT);
+                } else {
+                    self.error("Unexpected character '?'.".to_string());
+                }
+            }
             '/' => {
                 if self.is_match('/') {
                     loop {
@@ -380,6 +761,13 @@ impl<'a> Scanner<'a> {
                             self.advance();
                         }
                     }
+
+                    self.comments.push(Comment {
+                        line: self.start_line,
+                        text: self.source[self.start as usize + 2..self.current as usize]
+                            .iter()
+                            .collect(),
+                    });
                 } else {
                     self.add_token_no_literal(TokenType::SLASH)
                 }
@@ -387,16 +775,26 @@ impl<'a> Scanner<'a> {
             ' ' => {}
             '\r' => {}
             '\t' => {}
-            '\n' => self.line += 1,
-            '"' => self.string(),
+            '\n' => self.newline(),
+            '"' => {
+                if self.peek() == '"' && self.peek_next() == '"' {
+                    self.advance();
+                    self.advance();
+                    self.heredoc();
+                } else {
+                    self.string();
+                }
+            }
 
             _ => {
                 if Self::is_digit(c) {
                     self.number()
+                } else if c == 'r' && self.raw_string_hashes().is_some() {
+                    self.raw_string()
                 } else if Self::is_alpha(c) {
                     self.identifier()
                 } else {
-                    // error
+                    self.error(format!("Unexpected character '{}'.", c));
                 }
             }
         }
@@ -407,15 +805,14 @@ impl<'a> Scanner<'a> {
             if self.peek() == '"' || self.is_at_end() {
                 break;
             } else {
-                if self.peek() == '\n' {
-                    self.line += 1;
+                if self.advance() == '\n' {
+                    self.newline();
                 }
-                self.advance();
             }
         }
 
         if self.is_at_end() {
-            // TODO ERROR
+            self.error("Unterminated string.".to_string());
             return;
         }
 
@@ -432,6 +829,134 @@ impl<'a> Scanner<'a> {
         )
     }
 
+    // After an `r`, counts the `#`s of a raw string opener like `r"` or `r#"`.
+    // Anything else means the `r` starts an identifier.
+    fn raw_string_hashes(&self) -> Option<usize> {
+        let mut hashes = 0;
+
+        while self.source.get(self.current as usize + hashes) == Some(&'#') {
+            hashes += 1;
+        }
+
+        match self.source.get(self.current as usize + hashes) {
+            Some('"') => Some(hashes),
+            _ => None,
+        }
+    }
+
+    // Raw strings take their contents as written. `r#"..."#` ends only at a quote
+    // followed by as many `#`s as it opened with, so it can contain `"`.
+    fn raw_string(&mut self) {
+        let hashes = self.raw_string_hashes().unwrap_or_default();
+
+        // Opening #s and "
+        for _ in 0..=hashes {
+            self.advance();
+        }
+
+        loop {
+            if self.is_at_end() {
+                self.error("Unterminated raw string.".to_string());
+                return;
+            }
+
+            if self.peek() == '"' && self.closes_raw_string(hashes) {
+                break;
+            }
+
+            if self.advance() == '\n' {
+                self.newline();
+            }
+        }
+
+        // Closing " and #s
+        for _ in 0..=hashes {
+            self.advance();
+        }
+
+        let value = self.current_string();
+        let chars: Vec<char> = value.chars().collect();
+
+        self.add_token(
+            TokenType::STRING,
+            Some(Value::String {
+                value: chars[hashes + 2..chars.len() - hashes - 1].iter().collect(),
+            }),
+        )
+    }
+
+    fn closes_raw_string(&self, hashes: usize) -> bool {
+        (1..=hashes).all(|offset| self.source.get(self.current as usize + offset) == Some(&'#'))
+    }
+
+    // A `"""` string runs until the next `"""`, with the indentation common to its
+    // lines removed so it can be indented along with the surrounding code
+    fn heredoc(&mut self) {
+        loop {
+            if self.is_at_end() {
+                self.error("Unterminated string.".to_string());
+                return;
+            }
+
+            if self.peek() == '"' && self.peek_next() == '"' && self.peek_after_next() == '"' {
+                break;
+            }
+
+            if self.advance() == '\n' {
+                self.newline();
+            }
+        }
+
+        // Closing """
+        self.advance();
+        self.advance();
+        self.advance();
+
+        let value = self.current_string();
+        let chars: Vec<char> = value.chars().collect();
+        let contents: String = chars[3..chars.len() - 3].iter().collect();
+
+        self.add_token(
+            TokenType::STRING,
+            Some(Value::String {
+                value: Self::dedent(&contents),
+            }),
+        )
+    }
+
+    // Drops the line break after the opening quotes and the indentation before the
+    // closing ones, then strips the indentation shared by every non-blank line
+    fn dedent(contents: &str) -> String {
+        let mut lines: Vec<&str> = contents.split('\n').collect();
+
+        if lines.len() > 1 && lines[0].trim().is_empty() {
+            lines.remove(0);
+        }
+        if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+            lines.pop();
+        }
+
+        let indent = lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.chars().take_while(|c| *c == ' ' || *c == '\t').count())
+            .min()
+            .unwrap_or(0);
+
+        lines
+            .iter()
+            .map(|line| {
+                if line.trim().is_empty() {
+                    ""
+                } else {
+                    // The indentation is all spaces and tabs, so this is a char boundary
+                    &line[indent..]
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
     fn number(&mut self) {
         loop {
             if Self::is_digit(self.peek()) {
@@ -479,16 +1004,41 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    // Errors are reported where the offending token starts, which for an
+    // unterminated string can be several lines before where we noticed
+    fn error(&mut self, message: String) {
+        self.errors.push(ScanError {
+            line: self.start_line,
+            column: self.start_column,
+            message,
+            span: Span::new(
+                self.offsets[self.start as usize],
+                self.offsets[self.current as usize],
+            ),
+        });
+    }
+
+    // Called once we have consumed a '\n'
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn add_token_no_literal(&mut self, ttype: TokenType) {
         self.add_token(ttype, None)
     }
 
     fn add_token(&mut self, ttype: TokenType, literal: Option<Value>) {
         self.tokens.push(Token {
-            ttype: ttype,
-            literal: literal,
+            ttype,
+            literal,
             lexeme: self.current_string(),
-            line: self.line,
+            line: self.start_line,
+            column: self.start_column,
+            span: Span::new(
+                self.offsets[self.start as usize],
+                self.offsets[self.current as usize],
+            ),
         });
     }
 
@@ -525,22 +1075,29 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 > self.source.len() as i64 {
+        if self.current + 1 >= self.source.len() as i64 {
+            '\0'
+        } else {
+            self.source[(self.current + 1) as usize]
+        }
+    }
+
+    fn peek_after_next(&self) -> char {
+        if self.current + 2 >= self.source.len() as i64 {
             '\0'
         } else {
-            self.source
-                .chars()
-                .nth((self.current + 1) as usize)
-                .unwrap()
+            self.source[(self.current + 2) as usize]
         }
     }
 
     fn current_string(&self) -> String {
-        self.source[self.start as usize..self.current as usize].to_string()
+        self.source[self.start as usize..self.current as usize]
+            .iter()
+            .collect()
     }
 
     fn current_char(&self) -> char {
-        self.source.chars().nth((self.current) as usize).unwrap()
+        self.source[self.current as usize]
     }
 
     fn is_alpha_numeric(c: char) -> bool {
@@ -548,10 +1105,123 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_digit(c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
     fn is_alpha(c: char) -> bool {
-        (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+        c.is_ascii_alphabetic() || c == '_'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scanner, Span, TokenType, Value};
+
+    fn string_literal(source: &str) -> Value {
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        assert_eq!(TokenType::STRING, tokens[0].ttype);
+        tokens[0].literal.clone().unwrap()
+    }
+
+    #[test]
+    fn it_scans_raw_strings_as_written() {
+        assert_eq!(
+            Value::String {
+                value: "C:\\path\\n".to_string()
+            },
+            string_literal("r\"C:\\path\\n\"")
+        );
+        assert_eq!(
+            Value::String {
+                value: "say \"hi\"".to_string()
+            },
+            string_literal("r#\"say \"hi\"\"#")
+        );
+    }
+
+    #[test]
+    fn it_still_scans_identifiers_starting_with_r() {
+        let (tokens, _) = Scanner::new("return r; rest").scan_tokens();
+
+        assert_eq!(TokenType::RETURN, tokens[0].ttype);
+        assert_eq!(TokenType::IDENTIFIER, tokens[1].ttype);
+        assert_eq!("rest", tokens[3].lexeme);
+    }
+
+    #[test]
+    fn it_strips_common_indentation_from_heredocs() {
+        assert_eq!(
+            Value::String {
+                value: "<p>\n  hi\n\n</p>".to_string()
+            },
+            string_literal("\"\"\"\n    <p>\n      hi\n\n    </p>\n    \"\"\"")
+        );
+    }
+
+    #[test]
+    fn it_counts_lines_inside_multi_line_strings() {
+        let (tokens, _) = Scanner::new("\"\"\"\n  a\n  b\n\"\"\";\nr\"x\ny\";\nz").scan_tokens();
+
+        assert_eq!(4, tokens[1].line);
+        assert_eq!(6, tokens[3].line);
+        assert_eq!(7, tokens[4].line);
+    }
+
+    #[test]
+    fn it_places_multi_line_tokens_where_they_start() {
+        let (tokens, _) = Scanner::new("print \"a\nb\" + 1;\n  r\"x\ny\";").scan_tokens();
+
+        assert_eq!((1, 7), (tokens[1].line, tokens[1].column));
+        assert_eq!((2, 4), (tokens[2].line, tokens[2].column));
+        assert_eq!((3, 3), (tokens[5].line, tokens[5].column));
+        assert_eq!((4, 3), (tokens[6].line, tokens[6].column));
+    }
+
+    #[test]
+    fn it_reports_unexpected_characters_and_keeps_scanning() {
+        let (tokens, errors) = Scanner::new("var a = 1;\n  a # 2 $;").scan_tokens();
+
+        assert_eq!(2, errors.len());
+        assert_eq!((2, 5), (errors[0].line, errors[0].column));
+        assert_eq!("Unexpected character '#'.", errors[0].message);
+        assert_eq!((2, 9), (errors[1].line, errors[1].column));
+        assert_eq!(
+            vec![
+                TokenType::IDENTIFIER,
+                TokenType::NUMBER,
+                TokenType::SEMICOLON,
+                TokenType::EOF
+            ],
+            tokens[5..]
+                .iter()
+                .map(|token| token.ttype)
+                .collect::<Vec<TokenType>>()
+        );
+    }
+
+    #[test]
+    fn it_reports_unterminated_strings_where_they_start() {
+        let (_, errors) = Scanner::new("print 1;\nprint \"abc;\nprint 2;").scan_tokens();
+
+        assert_eq!(1, errors.len());
+        assert_eq!((2, 7), (errors[0].line, errors[0].column));
+        assert_eq!("Unterminated string.", errors[0].message);
+    }
+
+    #[test]
+    fn it_records_the_column_and_byte_span_of_tokens() {
+        let (tokens, _) = Scanner::new("var x = \"é\";\n  print x;").scan_tokens();
+
+        assert_eq!((1, 5), (tokens[1].line, tokens[1].column));
+        assert_eq!(Span::new(4, 5), tokens[1].span);
+        // Spans count bytes, columns count characters
+        assert_eq!(Span::new(8, 12), tokens[3].span);
+        assert_eq!((1, 12), (tokens[4].line, tokens[4].column));
+        assert_eq!((2, 3), (tokens[5].line, tokens[5].column));
+        assert_eq!(Span::new(16, 21), tokens[5].span);
+        assert_eq!((2, 9), (tokens[6].line, tokens[6].column));
+        assert_eq!(Span::new(22, 23), tokens[6].span);
+        assert_eq!(TokenType::EOF, tokens[8].ttype);
+        assert_eq!(Span::new(24, 24), tokens[8].span);
     }
 }
@@ -0,0 +1,33 @@
+use std::{env, fs, process::Command};
+
+// Runs the interpreter on `source` as a script, returning its exit code and stderr
+fn run(name: &str, source: &str) -> (Option<i32>, String) {
+    let path = env::temp_dir().join(format!("rustlox-{}-{}.lox", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rustlox"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&path);
+
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn it_renders_an_error_in_an_if_condition_as_a_diagnostic() {
+    let (code, stderr) = run("if-condition", "var x = 1;\nif (x + \"a\" > 1) print 1;\n");
+
+    assert_eq!(Some(70), code);
+    assert!(stderr.starts_with("error[E0005]: "), "{}", stderr);
+    assert!(
+        stderr.contains("2 | if (x + \"a\" > 1) print 1;\n  |       ^\n"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(!stderr.contains("RuntimeError"), "{}", stderr);
+}